@@ -0,0 +1,72 @@
+// animations are exported as gif, the png crate can not write apng
+
+use std::fs::File;
+use std::io::Write;
+
+use gif::{DisposalMethod, Encoder, Frame, Repeat, SetParameter};
+
+use draw::*;
+use error::Error;
+
+/// Browsers show gif frames with a delay below this (in 10ms) slower instead.
+const MIN_DELAY: u32 = 2;
+
+pub fn save_gif(filename: String, frames: &[(Image, u32)]) -> Result<(), Error> {
+    write_gif(File::create(filename)?, frames)
+}
+
+/// Encodes `frames`, each an image and how long it is shown in milliseconds,
+/// as a gif that loops forever.
+pub fn write_gif<T: Write>(data: T, frames: &[(Image, u32)]) -> Result<(), Error> {
+    let (width, height) = frames.first().map(|(x, _)| x.dimensions()).unwrap_or((1, 1));
+    let mut encoder = Encoder::new(data, width as u16, height as u16, &[])?;
+    encoder.set(Repeat::Infinite)?;
+
+    for (image, duration) in frames {
+        let mut pixels = image.clone().into_raw();
+        let mut frame = Frame::from_rgba(image.width() as u16, image.height() as u16, &mut pixels);
+        let delay = std::cmp::max(duration.saturating_add(5) / 10, MIN_DELAY);
+        frame.delay = std::cmp::min(delay, u16::MAX as u32) as u16;
+        // clear the previous frame, otherwise it shows through transparent pixels
+        frame.dispose = DisposalMethod::Background;
+        encoder.write_frame(&frame)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gif::{ColorOutput, Decoder};
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn write_gif_frames() {
+        let red: Image = ImageBuffer::from_pixel(4, 4, Rgba([255, 0, 0, 255]));
+        let mut blue: Image = ImageBuffer::from_pixel(4, 4, Rgba([0, 0, 255, 255]));
+        blue.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+
+        let mut written: Vec<u8> = Vec::new();
+        write_gif(&mut written, &[(red.clone(), 100), (blue, 10), (red, u32::MAX)]).unwrap();
+
+        let mut decoder = Decoder::new(&written[..]);
+        decoder.set(ColorOutput::RGBA);
+        let mut reader = decoder.read_info().unwrap();
+
+        let first = reader.read_next_frame().unwrap().unwrap();
+        assert_eq!(first.delay, 10);
+        assert_eq!(&first.buffer[..4], &[255, 0, 0, 255]);
+
+        let second = reader.read_next_frame().unwrap().unwrap();
+        assert_eq!(second.delay, 2);
+        assert_eq!(second.buffer[3], 0);
+        assert_eq!(&second.buffer[4..8], &[0, 0, 255, 255]);
+
+        // delays longer than a gif can hold are cut off
+        let third = reader.read_next_frame().unwrap().unwrap();
+        assert_eq!(third.delay, u16::MAX);
+
+        assert!(reader.read_next_frame().unwrap().is_none());
+    }
+}
@@ -1,15 +1,135 @@
-use std::io::Error;
+use std::io::Write;
+use error::Error;
 use mem_read::*;
+use mem_write::*;
+
+pub const NODE_ESCAPE: u8 = 0xFD;
+pub const NODE_START: u8 = 0xFE;
+pub const NODE_END: u8 = 0xFF;
+
+// https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/framework/core/binarytree.cpp
+/// A node borrowed from the input, its properties are still escaped and only
+/// resolved while reading them through `props`.
+pub struct RawNode<'a> {
+    offset: usize,
+    node_type: u8,
+    props: &'a [u8],
+    depth: usize,
+}
+
+impl<'a> RawNode<'a> {
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn node_type(&self) -> u8 {
+        self.node_type
+    }
+
+    /// The properties of this node, without the node type.
+    pub fn props(&self) -> NodeReader<'a> {
+        NodeReader { data: self.props, position: 0, offset: self.offset + 2 }
+    }
+}
+
+/// Reads the escaped properties of a node.
+pub struct NodeReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    offset: usize,
+}
+
+impl<'a> MemRead for NodeReader<'a> {
+    fn read_bytes(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let mut position = self.position;
+        for byte in buffer.iter_mut() {
+            if self.data.get(position) == Some(&NODE_ESCAPE) {
+                position += 1;
+            }
+
+            *byte = match self.data.get(position) {
+                Some(&x) => x,
+                None => return Err(Error::Truncated { offset: self.position() })
+            };
+            position += 1;
+        }
+
+        self.position = position;
+        Ok(())
+    }
+
+    fn position(&self) -> usize {
+        self.offset + self.position
+    }
+}
+
+/// Walks the nodes of a tree in a single pass over the input, children are read
+/// one after another while their parent is being parsed so nothing but the
+/// currently open nodes is kept around.
+pub struct TreeReader<'a> {
+    data: MemReader<'a>,
+    depth: usize,
+}
+
+impl<'a> TreeReader<'a> {
+    pub fn new(data: MemReader<'a>) -> TreeReader<'a> {
+        TreeReader { data, depth: 0 }
+    }
+
+    /// Reads the node starting at the current position.
+    pub fn next_node(&mut self) -> Result<RawNode<'a>, Error> {
+        let offset = self.data.position();
+        let start = self.data.get::<u8>()?;
+        if start != NODE_START {
+            return Err(Error::UnknownNodeType { node_type: start, offset });
+        }
+
+        self.depth += 1;
+        let node_type = self.data.get()?;
+
+        let remaining = self.data.remaining();
+        let mut size = 0;
+        loop {
+            match remaining.get(size) {
+                Some(&NODE_ESCAPE) => size += 2,
+                Some(&NODE_START) | Some(&NODE_END) => break,
+                Some(_) => size += 1,
+                None => return Err(Error::Truncated { offset: self.data.position() + remaining.len() })
+            }
+        }
+        self.data.skip(size)?;
+
+        Ok(RawNode { offset, node_type, props: &remaining[..size], depth: self.depth })
+    }
 
-const NODE_ESCAPE: u8 = 0xFD;
-const NODE_START: u8 = 0xFE;
-const NODE_END: u8 = 0xFF;
+    /// Reads the next child of `parent`, skipping whatever is left of the previous one.
+    pub fn next_child(&mut self, parent: &RawNode<'a>) -> Result<Option<RawNode<'a>>, Error> {
+        while self.depth >= parent.depth {
+            if self.depth == parent.depth && self.data.remaining().first() == Some(&NODE_START) {
+                return Ok(Some(self.next_node()?));
+            }
+
+            match self.data.get::<u8>()? {
+                NODE_ESCAPE => self.data.skip(1)?,
+                NODE_START => {
+                    self.depth += 1;
+                    self.data.skip(1)?; // node type
+                },
+                NODE_END => self.depth -= 1,
+                _ => {}
+            }
+        }
+
+        Ok(None)
+    }
+}
 
 pub trait BinaryTree {
     type Output;
     type Child;
 
-    fn read_node<T: MemRead>(data: &mut T) -> Result<Self::Output, Error>;
+    fn read_node(tree: &mut TreeReader) -> Result<Self::Output, Error>;
+    fn from_node<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<Self::Output, Error>;
 }
 
 pub trait HasChildren {
@@ -17,82 +137,68 @@ pub trait HasChildren {
     type Child;
 
     fn parse<T: MemRead>(data: &mut T, children: Vec<Self::Child>) -> Result<Self::Output, Error>;
-    fn parse_child<T: MemRead>(data: &mut T) -> Result<Self::Child, Error>;
+    fn parse_child<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<Self::Child, Error>;
 }
 
 impl <D: HasChildren> BinaryTree for D {
     type Output = D::Output;
     type Child = D::Child;
 
-    fn read_node<T: MemRead>(data: &mut T) -> Result<Self::Output, Error> {
-        //println!("read_node");
+    fn read_node(tree: &mut TreeReader) -> Result<Self::Output, Error> {
+        let node = tree.next_node()?;
+        Self::from_node(node, tree)
+    }
 
-        let mut buffer: Vec<u8> = Vec::new();
-        let mut children: Vec<Self::Child> = Vec::new();
+    fn from_node<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<Self::Output, Error> {
+        let mut children = Vec::new();
+        while let Some(child) = tree.next_child(&node)? {
+            children.push(Self::parse_child(child, tree)?);
+        }
 
-        loop {
-            let byte = match data.get::<u8>() {
-                Ok(b) => b,
-                Err(_) => break
-            };
+        Self::parse(&mut node.props(), children)
+    }
+}
 
-            match byte {
-                NODE_START => {
-                    children.push({
-
-                        let mut child_buffer: Vec<u8> = Vec::new();
-                        let mut depth = 0;
-
-                        loop {
-                            let s = match data.get::<u8>() {
-                                Ok(s) => s,
-                                Err(_) => break
-                            };
-
-                            match s {
-                                NODE_START =>{
-                                    depth += 1;
-                                    child_buffer.push(s);
-                                },
-                                NODE_END => {
-                                    if depth == 0 {
-                                        break;
-                                    } else {
-                                        depth -= 1;
-                                        child_buffer.push(s);
-                                    }
-                                },
-                                NODE_ESCAPE => {
-                                    if depth == 0 {
-                                        child_buffer.push(data.get::<u8>()?)
-                                    } else {
-                                        child_buffer.push(s);
-                                        child_buffer.push(data.get::<u8>()?)
-                                    }
-                                },
-                                _ => {
-                                    child_buffer.push(s)
-                                }
-                            }
-                        }
-
-                        //println!("depth: {}", depth);
-                        //println!("child_buffer: {:?}", &child_buffer[0..std::cmp::min(100, child_buffer.len())]);
-                        Self::parse_child(&mut child_buffer.as_ref() as &mut &[u8])?
-
-                    })
-                },
-                NODE_END => {
-                    break;
-                },
-                NODE_ESCAPE => {
-                    buffer.push(data.get::<u8>()?)
-                },
-                _ => buffer.push(byte)
-            }
+pub fn write_node_start<T: Write>(data: &mut T, node_type: u8, props: &[u8]) -> Result<(), Error> {
+    data.put(NODE_START)?;
+    data.put(node_type)?;
+    for &byte in props {
+        if byte == NODE_ESCAPE || byte == NODE_START || byte == NODE_END {
+            data.put(NODE_ESCAPE)?;
         }
+        data.put(byte)?;
+    }
+    Ok(())
+}
 
-        //println!("parent_buffer: {:?}", &buffer[0..std::cmp::min(100, buffer.len())]);
-        Ok(Self::parse(&mut buffer.as_ref() as &mut &[u8], children)?)
+pub fn write_node_end<T: Write>(data: &mut T) -> Result<(), Error> {
+    data.put(NODE_END)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_escaped_props_and_children() {
+        let mut data: Vec<u8> = Vec::new();
+        write_node_start(&mut data, 1, &[NODE_START, 7, NODE_ESCAPE]).unwrap();
+        write_node_start(&mut data, 2, &[NODE_END]).unwrap();
+        write_node_end(&mut data).unwrap();
+        write_node_end(&mut data).unwrap();
+        assert_eq!(data, [NODE_START, 1, NODE_ESCAPE, NODE_START, 7, NODE_ESCAPE, NODE_ESCAPE, NODE_START, 2, NODE_ESCAPE, NODE_END, NODE_END, NODE_END]);
+
+        let tree = &mut TreeReader::new(MemReader::new(&data));
+        let node = tree.next_node().unwrap();
+        assert_eq!(node.node_type(), 1);
+        assert_eq!(node.props().get::<[u8; 3]>().unwrap(), [NODE_START, 7, NODE_ESCAPE]);
+
+        let child = tree.next_child(&node).unwrap().unwrap();
+        assert_eq!(child.offset(), 7);
+        assert_eq!(child.node_type(), 2);
+        assert_eq!(child.props().get::<u8>().unwrap(), NODE_END);
+        assert!(child.props().get::<[u8; 2]>().is_err());
+        assert!(tree.next_child(&node).unwrap().is_none());
     }
-}
\ No newline at end of file
+}
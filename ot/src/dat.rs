@@ -2,9 +2,10 @@
 
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Error, Read};
+use std::io::Read;
 use image::{ImageBuffer};
 
+use error::Error;
 use mem_read::*;
 
 use spr::*;
@@ -13,10 +14,14 @@ use rand::*;
 use draw::*;
 use size::*;
 use point::*;
+use version::ClientVersion;
 
 use num_traits::{FromPrimitive, ToPrimitive};
 
-#[derive(Debug, Eq, Hash, PartialEq, Primitive)]
+/// How opaque translucent things are drawn.
+const TRANSLUCENT_OPACITY: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Primitive)]
 pub enum DatAttributesHeader {
     Ground = 0,
     GroundBorder = 1,
@@ -61,6 +66,10 @@ pub enum DatAttributesHeader {
     NotPreWalkable = 100,
     Unknown1 = 101,
 
+    // only in older clients
+    FloorChange = 252,
+    Chargeable = 253,
+
     Usable = 254,
 
     LastAttr = 255,
@@ -133,67 +142,132 @@ pub enum DatAttributes {
     NotPreWalkable,
     Unknown1,
 
+    FloorChange,
+    Chargeable,
+
     Usable,
 
     LastAttr,
 }
 
+// https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/client/thingtype.cpp#L161
+impl DatAttributesHeader {
+    /// Maps an attribute id as stored by `version` onto the 10.00+ ids used by this enum.
+    fn from_version(attribute: u8, version: ClientVersion) -> Option<DatAttributesHeader> {
+        if attribute == DatAttributesHeader::LastAttr as u8 || version >= ClientVersion(1000) {
+            return DatAttributesHeader::from_u8(attribute);
+        }
+
+        // older layouts are mapped onto the 8.6 one first
+        let attribute = if version >= ClientVersion(860) {
+            attribute
+        } else if version >= ClientVersion(780) {
+            // 7.8 added charges at 8
+            match attribute {
+                8 => return Some(DatAttributesHeader::Chargeable),
+                x if x > 8 => x - 1,
+                x => x
+            }
+        } else if version >= ClientVersion(755) {
+            match attribute {
+                23 => return Some(DatAttributesHeader::FloorChange),
+                x => x
+            }
+        } else {
+            let attribute = match attribute {
+                1..=15 => attribute + 1, // there were no ground borders
+                16 => 21, // light
+                17 => return Some(DatAttributesHeader::FloorChange),
+                18 => 30, // full ground
+                19 => 25, // elevation
+                20 => 24, // displacement
+                22 => 28, // minimap color
+                23 => 20, // rotateable
+                24 => 26, // lying corpse
+                25 => 17, // hangable
+                26 => 18, // hook south
+                27 => 19, // hook east
+                28 => 27, // animate always
+                x => x
+            };
+
+            // multi use and force use are swapped
+            match attribute {
+                6 => 7,
+                7 => 6,
+                x => x
+            }
+        };
+
+        // 10.00 added no move animation at 16
+        match attribute {
+            16..=99 => DatAttributesHeader::from_u8(attribute + 1),
+            x => DatAttributesHeader::from_u8(x)
+        }
+    }
+}
+
 impl DatAttributes {
     fn new<T: MemRead>(
         header: &DatAttributesHeader,
-        category: ThingCategory,
+        _category: ThingCategory,
+        options: DatOptions,
         data: &mut T,
     ) -> Result<DatAttributes, Error> {
-        let r = match header {
-            &DatAttributesHeader::Ground => DatAttributes::Ground(data.get()?),
-            &DatAttributesHeader::Writeable => DatAttributes::Writeable(data.get()?),
-            &DatAttributesHeader::WriteableOnce => DatAttributes::WriteableOnce(data.get()?),
-            &DatAttributesHeader::MinimapColor => DatAttributes::MinimapColor(data.get()?),
-            &DatAttributesHeader::LensHelp => DatAttributes::LensHelp(data.get()?),
-            &DatAttributesHeader::Cloth => DatAttributes::Cloth(data.get()?),
-            &DatAttributesHeader::DefaultAction => DatAttributes::DefaultAction(data.get()?),
-
-            &DatAttributesHeader::GroundBorder => DatAttributes::GroundBorder,
-            &DatAttributesHeader::OnBottom => DatAttributes::OnBottom,
-            &DatAttributesHeader::OnTop => DatAttributes::OnTop,
-            &DatAttributesHeader::Container => DatAttributes::Container,
-            &DatAttributesHeader::Stackable => DatAttributes::Stackable,
-            &DatAttributesHeader::ForceUse => DatAttributes::ForceUse,
-            &DatAttributesHeader::MultiUse => DatAttributes::MultiUse,
-            &DatAttributesHeader::FluidContainer => DatAttributes::FluidContainer,
-            &DatAttributesHeader::Splash => DatAttributes::Splash,
-            &DatAttributesHeader::NotWalkable => DatAttributes::NotWalkable,
-            &DatAttributesHeader::NotMoveable => DatAttributes::NotMoveable,
-            &DatAttributesHeader::BlockProjectile => DatAttributes::BlockProjectile,
-            &DatAttributesHeader::NotPathable => DatAttributes::NotPathable,
-            &DatAttributesHeader::NoMoveAnimation => DatAttributes::NoMoveAnimation,
-            &DatAttributesHeader::Pickupable => DatAttributes::Pickupable,
-            &DatAttributesHeader::Hangable => DatAttributes::Hangable,
-            &DatAttributesHeader::HookSouth => DatAttributes::HookSouth,
-            &DatAttributesHeader::HookEast => DatAttributes::HookEast,
-            &DatAttributesHeader::Rotateable => DatAttributes::Rotateable,
-            &DatAttributesHeader::DontHide => DatAttributes::DontHide,
-            &DatAttributesHeader::Translucent => DatAttributes::Translucent,
-            &DatAttributesHeader::LyingCorpse => DatAttributes::LyingCorpse,
-            &DatAttributesHeader::AnimateAlways => DatAttributes::AnimateAlways,
-            &DatAttributesHeader::FullGround => DatAttributes::FullGround,
-            &DatAttributesHeader::Look => DatAttributes::Look,
-            &DatAttributesHeader::Wrapable => DatAttributes::Wrapable,
-            &DatAttributesHeader::Unwrapable => DatAttributes::Unwrapable,
-            &DatAttributesHeader::TopEffect => DatAttributes::TopEffect,
-            &DatAttributesHeader::Usable => DatAttributes::Usable,
-
-            &DatAttributesHeader::Light => DatAttributes::Light(LightInfo {
+        let r = match *header {
+            DatAttributesHeader::Ground => DatAttributes::Ground(data.get()?),
+            DatAttributesHeader::Writeable => DatAttributes::Writeable(data.get()?),
+            DatAttributesHeader::WriteableOnce => DatAttributes::WriteableOnce(data.get()?),
+            DatAttributesHeader::MinimapColor => DatAttributes::MinimapColor(data.get()?),
+            DatAttributesHeader::LensHelp => DatAttributes::LensHelp(data.get()?),
+            DatAttributesHeader::Cloth => DatAttributes::Cloth(data.get()?),
+            DatAttributesHeader::DefaultAction => DatAttributes::DefaultAction(data.get()?),
+
+            DatAttributesHeader::GroundBorder => DatAttributes::GroundBorder,
+            DatAttributesHeader::OnBottom => DatAttributes::OnBottom,
+            DatAttributesHeader::OnTop => DatAttributes::OnTop,
+            DatAttributesHeader::Container => DatAttributes::Container,
+            DatAttributesHeader::Stackable => DatAttributes::Stackable,
+            DatAttributesHeader::ForceUse => DatAttributes::ForceUse,
+            DatAttributesHeader::MultiUse => DatAttributes::MultiUse,
+            DatAttributesHeader::FluidContainer => DatAttributes::FluidContainer,
+            DatAttributesHeader::Splash => DatAttributes::Splash,
+            DatAttributesHeader::NotWalkable => DatAttributes::NotWalkable,
+            DatAttributesHeader::NotMoveable => DatAttributes::NotMoveable,
+            DatAttributesHeader::BlockProjectile => DatAttributes::BlockProjectile,
+            DatAttributesHeader::NotPathable => DatAttributes::NotPathable,
+            DatAttributesHeader::NoMoveAnimation => DatAttributes::NoMoveAnimation,
+            DatAttributesHeader::Pickupable => DatAttributes::Pickupable,
+            DatAttributesHeader::Hangable => DatAttributes::Hangable,
+            DatAttributesHeader::HookSouth => DatAttributes::HookSouth,
+            DatAttributesHeader::HookEast => DatAttributes::HookEast,
+            DatAttributesHeader::Rotateable => DatAttributes::Rotateable,
+            DatAttributesHeader::DontHide => DatAttributes::DontHide,
+            DatAttributesHeader::Translucent => DatAttributes::Translucent,
+            DatAttributesHeader::LyingCorpse => DatAttributes::LyingCorpse,
+            DatAttributesHeader::AnimateAlways => DatAttributes::AnimateAlways,
+            DatAttributesHeader::FullGround => DatAttributes::FullGround,
+            DatAttributesHeader::Look => DatAttributes::Look,
+            DatAttributesHeader::Wrapable => DatAttributes::Wrapable,
+            DatAttributesHeader::Unwrapable => DatAttributes::Unwrapable,
+            DatAttributesHeader::TopEffect => DatAttributes::TopEffect,
+            DatAttributesHeader::Usable => DatAttributes::Usable,
+            DatAttributesHeader::FloorChange => DatAttributes::FloorChange,
+            DatAttributesHeader::Chargeable => DatAttributes::Chargeable,
+
+            DatAttributesHeader::Light => DatAttributes::Light(LightInfo {
                 intensity: data.get()?,
                 color: data.get()?,
             }),
-            &DatAttributesHeader::Displacement => DatAttributes::Displacement(Vector2 {
+            // before 7.55 every displacement was 8, 8
+            DatAttributesHeader::Displacement if options.version < ClientVersion(755) => DatAttributes::Displacement(Vector2 { x: 8, y: 8 }),
+            DatAttributesHeader::Displacement => DatAttributes::Displacement(Vector2 {
                 x: data.get()?,
                 y: data.get()?,
             }),
 
-            &DatAttributesHeader::Elevation => DatAttributes::Elevation(data.get()?),
-            &DatAttributesHeader::Market => DatAttributes::Market(MarketInfo {
+            DatAttributesHeader::Elevation => DatAttributes::Elevation(data.get()?),
+            DatAttributesHeader::Market => DatAttributes::Market(MarketInfo {
                 category: data.get()?,
                 trade_as: data.get()?,
                 show_as: data.get()?,
@@ -202,7 +276,7 @@ impl DatAttributes {
                 required_level: data.get()?,
             }),
 
-            _ => panic!("unknown item attribute"),
+            _ => return Err(Error::UnknownAttribute { attribute: *header as u8, offset: data.position() - 1 }),
         };
         Ok(r)
     }
@@ -271,7 +345,7 @@ impl FrameGroupAnimator {
     }
 }
 
-#[derive(Primitive, Eq, PartialEq, Hash)]
+#[derive(Clone, Copy, Debug, Primitive, Eq, PartialEq, Hash)]
 pub enum FrameGroupType {
     Idle = 0,
     Moving = 1,
@@ -292,7 +366,7 @@ pub struct FrameGroup {
 }
 
 impl FrameGroup {
-    fn new<T: MemRead>(data: &mut T) -> Result<FrameGroup, Error> {
+    fn new<T: MemRead>(options: DatOptions, data: &mut T) -> Result<FrameGroup, Error> {
         let width = data.get::<u8>()?;
         let height = data.get::<u8>()?;
 
@@ -308,13 +382,13 @@ impl FrameGroup {
             layers: data.get()?,
             pattern_width: data.get()?,
             pattern_height: data.get()?,
-            pattern_depth: data.get()?,
+            pattern_depth: if options.version.has_pattern_z() { data.get()? } else { 1 },
             phases: data.get()?,
             animator: Option::None,
             sprites: Vec::new(),
         };
 
-        if frame_group.phases > 1 {
+        if frame_group.phases > 1 && options.version.has_enhanced_animations() {
             frame_group.animator = Some(FrameGroupAnimator::new(frame_group.phases, data)?);
         }
 
@@ -327,7 +401,8 @@ impl FrameGroup {
             * frame_group.phases as u32;
 
         for _ in 0..total_sprites {
-            frame_group.sprites.push(data.get()?);
+            let sprite = if options.extended { data.get()? } else { data.get::<u16>()? as u32 };
+            frame_group.sprites.push(sprite);
         }
 
         //println!("{:?}", frame_group);
@@ -336,22 +411,28 @@ impl FrameGroup {
     }
 
     fn get_texture_index(&self, l: i32, x: i32, y: i32, z: i32) -> i32 {
-        return ((l * self.pattern_depth as i32 + z)
+        ((l * self.pattern_depth as i32 + z)
             * self.pattern_height as i32 + y)
-            * self.pattern_width as i32 + x;
+            * self.pattern_width as i32 + x
     }
 
-    fn get_sprite_index(&self, w: i32, h: i32, l: i32, x: i32, y: i32, z: i32, a: i32) -> i32 {
+    /// The sprite id at a tile of the frame group, layers, patterns and phases
+    /// wrap around. None if the tile is outside of the frame group.
+    #[allow(clippy::too_many_arguments)]
+    fn get_sprite(&self, w: i32, h: i32, l: i32, x: i32, y: i32, z: i32, a: i32) -> Option<u32> {
+        if self.sprites.is_empty() || w < 0 || w >= self.width as i32 || h < 0 || h >= self.height as i32 {
+            return None;
+        }
+
         let index =
-            ((((((a % self.phases as i32)
-            *  self.pattern_depth as i32 + z)
-            * self.pattern_height as i32 + y)
-            * self.pattern_width as i32 + x)
-            * self.layers as i32 + l)
+            ((((((a.rem_euclid(self.phases as i32))
+            *  self.pattern_depth as i32 + z.rem_euclid(self.pattern_depth as i32))
+            * self.pattern_height as i32 + y.rem_euclid(self.pattern_height as i32))
+            * self.pattern_width as i32 + x.rem_euclid(self.pattern_width as i32))
+            * self.layers as i32 + l.rem_euclid(self.layers as i32))
             * self.height as i32 + h)
             * self.width as i32 + w;
-        assert!((index as usize) < self.sprites.len());
-        index
+        self.sprites.get(index as usize).cloned()
     }
 }
 
@@ -363,6 +444,9 @@ pub enum ThingCategory {
     Missile = 3,
 }
 
+/// All things of a dat file, by category and client id.
+pub type Things = HashMap<ThingCategory, HashMap<u16, Thing>>;
+
 pub struct Thing {
     id: u16,
     category: ThingCategory,
@@ -382,24 +466,200 @@ impl Thing {
 }
 
 impl Thing {
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn category(&self) -> ThingCategory {
+        self.category
+    }
+
+    pub fn has_attribute(&self, header: DatAttributesHeader) -> bool {
+        self.attributes.contains_key(&header)
+    }
+
+    pub fn get_attribute(&self, header: DatAttributesHeader) -> Option<&DatAttributes> {
+        self.attributes.get(&header)
+    }
+
+    pub fn elevation(&self) -> i32 {
+        match self.get_attribute(DatAttributesHeader::Elevation) {
+            Some(DatAttributes::Elevation(x)) => *x as i32,
+            _ => 0
+        }
+    }
+
+    pub fn displacement(&self) -> Point {
+        match self.get_attribute(DatAttributesHeader::Displacement) {
+            Some(DatAttributes::Displacement(x)) => Point::new(x.x as i32, x.y as i32),
+            _ => Point::new(0, 0)
+        }
+    }
+
+    pub fn ground_speed(&self) -> Option<u16> {
+        match self.get_attribute(DatAttributesHeader::Ground) {
+            Some(DatAttributes::Ground(x)) => Some(*x),
+            _ => None
+        }
+    }
+
+    /// None if the thing does not show on the minimap, a color of 0 means the same.
+    pub fn minimap_color(&self) -> Option<u8> {
+        match self.get_attribute(DatAttributesHeader::MinimapColor) {
+            Some(DatAttributes::MinimapColor(0)) => None,
+            Some(DatAttributes::MinimapColor(x)) => Some(*x as u8),
+            _ => None
+        }
+    }
+
+    /// Light intensity and color.
+    pub fn light(&self) -> Option<(u16, u16)> {
+        match self.get_attribute(DatAttributesHeader::Light) {
+            Some(DatAttributes::Light(x)) => Some((x.intensity, x.color)),
+            _ => None
+        }
+    }
+
+    /// The sprite ids of the first pattern and phase of a frame group, by layer, row and column.
+    pub fn first_sprites(&self, group: FrameGroupType) -> Vec<u32> {
+        let frame = match self.frame_groups.get(&group) {
+            Some(frame) => frame,
+            None => return Vec::new()
+        };
+
+        let mut sprites = Vec::new();
+        for l in 0..frame.layers {
+            for h in 0..frame.height {
+                for w in 0..frame.width {
+                    sprites.push(frame.get_sprite(w as _, h as _, l as _, 0, 0, 0, 0).unwrap_or(0));
+                }
+            }
+        }
+        sprites
+    }
+
+    pub fn pattern_size(&self) -> (i32, i32, i32) {
+        match self.frame_groups.get(&FrameGroupType::Idle) {
+            Some(frame) => (frame.pattern_width as i32, frame.pattern_height as i32, frame.pattern_depth as i32),
+            None => (1, 1, 1)
+        }
+    }
+
+    // https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/client/thingtype.h#L143
+    /// The order things are stacked in on a tile, lower values are drawn first.
+    pub fn stack_priority(&self) -> u8 {
+        if self.has_attribute(DatAttributesHeader::Ground) {
+            0
+        } else if self.has_attribute(DatAttributesHeader::GroundBorder) {
+            1
+        } else if self.has_attribute(DatAttributesHeader::OnBottom) {
+            2
+        } else if self.has_attribute(DatAttributesHeader::OnTop) {
+            3
+        } else if self.category == ThingCategory::Creature {
+            4
+        } else {
+            5
+        }
+    }
+
+    /// Draws all layers of the idle frame group with `dest` being the top left
+    /// corner of the tile the thing is on, larger things grow up and to the left.
+    /// Translucent things are drawn half transparent.
+    pub fn draw(&self, spr: &SpriteData, image: &mut Image, dest: Point, x: i32, y: i32, z: i32) {
+        let frame = match self.frame_groups.get(&FrameGroupType::Idle) {
+            Some(frame) => frame,
+            None => return
+        };
+
+        let opacity = if self.has_attribute(DatAttributesHeader::Translucent) { TRANSLUCENT_OPACITY } else { 1.0 };
+        let displacement = self.displacement();
+        for l in 0..frame.layers {
+            for h in 0..frame.height {
+                for w in 0..frame.width {
+                    let sprite = frame.get_sprite(w as _, h as _, l as _, x, y, z, 0);
+                    if let Some(sprite_image) = sprite.and_then(|x| spr.get_image(x)) {
+                        let sprite_pos = Point::new(dest.x - w as i32 * 32 - displacement.x, dest.y - h as i32 * 32 - displacement.y);
+                        image.blit_opacity(sprite_pos, sprite_image, opacity);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn get_texture(&self, spr: &SpriteData) -> Option<Image> {
+        self.get_texture_phase(spr, FrameGroupType::Idle, 0)
+    }
+
+    /// The number of animation phases of a frame group, 0 if the thing does not have it.
+    pub fn phases(&self, group: FrameGroupType) -> u8 {
+        self.frame_groups.get(&group).map(|x| x.phases).unwrap_or(0)
+    }
+
+    /// Every animation phase of the x, y and z pattern of a frame group together
+    /// with how long it is shown in milliseconds. Only the first layer is drawn.
+    /// Phases without durations in the dat use the client's default speed.
+    pub fn get_animation(&self, spr: &SpriteData, group: FrameGroupType, x: u8, y: u8, z: u8) -> Vec<(Image, u32)> {
+        let frame = match self.frame_groups.get(&group) {
+            Some(frame) => frame,
+            None => return Vec::new()
+        };
+
+        // https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/client/const.h#L34
+        let default_duration = if self.category == ThingCategory::Effect { 75 } else { 500 };
+        (0..frame.phases)
+            .filter_map(|phase| {
+                let duration = frame.animator.as_ref()
+                    .and_then(|x| x.frame_group_durations.get(phase as usize))
+                    .map(|x| ((x.minimum as u64 + x.maximum as u64) / 2) as u32)
+                    .unwrap_or(default_duration);
+                Some((self.get_image(spr, group, 0, x, y, z, phase)?, duration))
+            })
+            .collect()
+    }
 
-    pub fn get_texture(&self, spr: &SpriteData) -> Image {
-        let frame = &self.frame_groups[&FrameGroupType::Idle];
+    /// One layer of a frame group with the given pattern and phase, composed
+    /// into a single image of width x height sprites.
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_image(&self, spr: &SpriteData, group: FrameGroupType, layer: u8, x: u8, y: u8, z: u8, phase: u8) -> Option<Image> {
+        let frame = self.frame_groups.get(&group)?;
+        let mut image: Image = ImageBuffer::new(32 * frame.width as u32, 32 * frame.height as u32);
+        for h in 0..frame.height {
+            for w in 0..frame.width {
+                let sprite = frame.get_sprite(w as _, h as _, layer as _, x as _, y as _, z as _, phase as _);
+                if let Some(sprite_image) = sprite.and_then(|x| spr.get_image(x)) {
+                    let sprite_pos = Point::new((frame.width as i32 - w as i32 - 1) * 32, (frame.height as i32 - h as i32 - 1) * 32);
+                    image.blit(sprite_pos, sprite_image);
+                }
+            }
+        }
+        Some(image)
+    }
+
+    pub fn layers(&self, group: FrameGroupType) -> u8 {
+        self.frame_groups.get(&group).map(|x| x.layers).unwrap_or(0)
+    }
+
+    /// All patterns and layers of a frame group phase laid out in one image,
+    /// None if the thing does not have the frame group or it is too large.
+    pub fn get_texture_phase(&self, spr: &SpriteData, group: FrameGroupType, phase: u8) -> Option<Image> {
+        let frame = self.frame_groups.get(&group)?;
+        if frame.sprites.is_empty() {
+            return None;
+        }
         //println!("ID: {}", &self.id);
         //println!("category: {:?}", &self.category);
 
         let mut texture_layers = 1;
-        let mut num_layers = frame.layers;
         if self.category == ThingCategory::Creature && frame.layers >= 2 {
             texture_layers = 5;
-            num_layers = 5;
         }
 
         //println!("{:?}", self.attributes);
 
-        let index_size = texture_layers * frame.pattern_width * frame.pattern_height * frame.pattern_depth;
+        let index_size = texture_layers as i32 * frame.pattern_width as i32 * frame.pattern_height as i32 * frame.pattern_depth as i32;
         //println!("frame: {:?}", frame);
-        let texture_size = Thing::get_best_texture_dimension(&frame, frame.width as _, frame.height as _, index_size as _);
+        let texture_size = Thing::get_best_texture_dimension(frame.width as _, frame.height as _, index_size)?;
         //println!("texture_size: {:?}", texture_size);
 
         let mut full_image: Image = ImageBuffer::new(32 * texture_size.width as u32, 32 * texture_size.height as u32);
@@ -418,9 +678,9 @@ impl Thing {
                         for h in 0..frame.height {
                             for w in 0..frame.width {
                                 //println!("w, h: {} {}", h, w);
-                                let sprite_index = frame.get_sprite_index(w as _, h as _, if sprite_mask { 1i32 } else { l as i32 }, x as _, y as _, z as _, 0 /* TODO: animationPhase */);
-                                //println!("sprite_index: {} {}", sprite_index, frame.sprites[sprite_index as usize]);
-                                let sprite_image_opt = spr.get_image(frame.sprites[sprite_index as usize]);
+                                let sprite = frame.get_sprite(w as _, h as _, if sprite_mask { 1i32 } else { l as i32 }, x as _, y as _, z as _, phase as _);
+                                //println!("sprite: {:?}", sprite);
+                                let sprite_image_opt = sprite.and_then(|x| spr.get_image(x));
                                 if let Some(sprite_image_original) = sprite_image_opt {
                                     let mut sprite_image = sprite_image_original.clone();
                                     if sprite_mask {
@@ -442,10 +702,11 @@ impl Thing {
         }
 
         //ImageBuffer::new(1, 1)
-        full_image
+        Some(full_image)
     }
 
-    fn get_best_texture_dimension(frame: &FrameGroup, mut w: i32, mut h: i32, count: i32) -> Size {
+    /// None if the sprites do not fit into a 32x32 sprites texture.
+    fn get_best_texture_dimension(mut w: i32, mut h: i32, count: i32) -> Option<Size> {
         const MAX: i32 = 32;
 
         let mut k = 1i32;
@@ -460,10 +721,13 @@ impl Thing {
         }
         h = k;
 
-        let num_sprites = w * h * count;
-        assert!(num_sprites <= MAX * MAX);
-        assert!(w <= MAX);
-        assert!(h <= MAX);
+        if w > MAX || h > MAX {
+            return None;
+        }
+        let num_sprites = match (w * h).checked_mul(count) {
+            Some(x) if x <= MAX * MAX => x,
+            _ => return None
+        };
 
         let mut i = w;
 
@@ -478,7 +742,7 @@ impl Thing {
                 }
                 if candidate_dimension.area() < best_dimension.area() ||
                     (candidate_dimension.area() == best_dimension.area() && candidate_dimension.width + candidate_dimension.height < best_dimension.width + best_dimension.height) {
-                    best_dimension = candidate_dimension.clone();
+                    best_dimension = candidate_dimension;
                 }
 
                 j <<= 1;
@@ -486,7 +750,27 @@ impl Thing {
             i <<= 1;
         }
 
-        best_dimension
+        Some(best_dimension)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DatOptions {
+    /// Picks the attribute ids and whether z patterns, frame groups and animators are stored.
+    pub version: ClientVersion,
+    /// Sprite ids are stored as u32 instead of u16.
+    pub extended: bool,
+}
+
+impl From<ClientVersion> for DatOptions {
+    fn from(version: ClientVersion) -> DatOptions {
+        DatOptions { version, extended: version.has_extended_sprites() }
+    }
+}
+
+impl Default for DatOptions {
+    fn default() -> DatOptions {
+        DatOptions::from(ClientVersion::LATEST)
     }
 }
 
@@ -497,12 +781,10 @@ const THING_CATEGORIES: &[ThingCategory] = &[
     ThingCategory::Missile,
 ];
 
-pub fn parse_items<T: MemRead>(
-    data: &mut T,
-) -> Result<HashMap<ThingCategory, HashMap<u16, Thing>>, Error> {
+pub fn parse_items<T: MemRead>(data: &mut T, options: DatOptions) -> Result<Things, Error> {
     let mut counts = HashMap::new();
     for &category in THING_CATEGORIES {
-        let count = data.get::<u16>()? + 1;
+        let count = data.get::<u16>()? as u32 + 1;
         //println!("count: {}", count);
         counts.insert(category, count);
     }
@@ -518,37 +800,46 @@ pub fn parse_items<T: MemRead>(
         let mut map = HashMap::new();
         for id in first_id..counts[&category] {
             //println!("id: {}/{} {:?}", id, counts[&category], category);
+            let id = id as u16;
             let mut thing = Thing::new(id, category);
 
             let n = DatAttributesHeader::LastAttr.to_u8().expect("Error");
             for _ in 0..n {
                 //println!("pos: {}", data.position());
-                let header =
-                    DatAttributesHeader::from_u8(data.get()?).expect("unknown dat attribute");
+                let attribute = data.get::<u8>()?;
+                let header = match DatAttributesHeader::from_version(attribute, options.version) {
+                    Some(x) => x,
+                    None => return Err(Error::UnknownAttribute { attribute, offset: data.position() - 1 })
+                };
                 //println!("header: {:?}", header);
                 if header == DatAttributesHeader::LastAttr {
                     break;
                 }
 
-                let attr = DatAttributes::new(&header, category, data)?;
+                let attr = DatAttributes::new(&header, category, options, data)?;
                 //println!(" > {:?} -> {:?}", header, attr);
                 thing.attributes.insert(header, attr);
             }
 
-            let group_count = if category == ThingCategory::Creature {
+            let has_frame_groups = category == ThingCategory::Creature && options.version.has_frame_groups();
+            let group_count = if has_frame_groups {
                 data.get::<u8>()?
             } else {
                 1
             };
             //println!("group_count: {}", group_count);
             for _ in 0..group_count {
-                let group_type = if category == ThingCategory::Creature {
-                    FrameGroupType::from_u8(data.get()?).expect("unknown frame group")
+                let group_type = if has_frame_groups {
+                    let group_type = data.get::<u8>()?;
+                    match FrameGroupType::from_u8(group_type) {
+                        Some(x) => x,
+                        None => return Err(Error::UnknownAttribute { attribute: group_type, offset: data.position() - 1 })
+                    }
                 } else {
                     FrameGroupType::Idle
                 };
 
-                let frame_group = FrameGroup::new(data)?;
+                let frame_group = FrameGroup::new(options, data)?;
 
                 thing
                     .frame_groups
@@ -564,12 +855,164 @@ pub fn parse_items<T: MemRead>(
     Ok(things)
 }
 
-pub fn parse(filename: String) -> Result<HashMap<ThingCategory, HashMap<u16, Thing>>, Error> {
+pub fn parse(filename: String) -> Result<Things, Error> {
+    parse_with_options(filename, DatOptions::default())
+}
+
+pub fn parse_with_options(filename: String, options: DatOptions) -> Result<Things, Error> {
     let mut file = File::open(filename)?;
     let mut data: Vec<u8> = Vec::new();
     file.read_to_end(&mut data)?;
-    let data: &mut &[u8] = &mut data.as_ref();
+    let data = &mut MemReader::new(&data);
+
+    data.get::<u32>()?; // signature
+    parse_items(data, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn attributes_of_older_versions() {
+        let pickupable = Some(DatAttributesHeader::Pickupable);
+        assert_eq!(DatAttributesHeader::from_version(17, ClientVersion(1098)), pickupable);
+        assert_eq!(DatAttributesHeader::from_version(16, ClientVersion(1098)), Some(DatAttributesHeader::NoMoveAnimation));
+        assert_eq!(DatAttributesHeader::from_version(16, ClientVersion(1000)), Some(DatAttributesHeader::NoMoveAnimation));
+        assert_eq!(DatAttributesHeader::from_version(16, ClientVersion(860)), pickupable);
+        assert_eq!(DatAttributesHeader::from_version(17, ClientVersion(780)), pickupable);
+        assert_eq!(DatAttributesHeader::from_version(8, ClientVersion(780)), Some(DatAttributesHeader::Chargeable));
+        assert_eq!(DatAttributesHeader::from_version(23, ClientVersion(772)), Some(DatAttributesHeader::FloorChange));
+        assert_eq!(DatAttributesHeader::from_version(15, ClientVersion(740)), pickupable);
+        assert_eq!(DatAttributesHeader::from_version(5, ClientVersion(710)), Some(DatAttributesHeader::MultiUse));
+        assert_eq!(DatAttributesHeader::from_version(16, ClientVersion(710)), Some(DatAttributesHeader::Light));
+        assert_eq!(DatAttributesHeader::from_version(255, ClientVersion(710)), Some(DatAttributesHeader::LastAttr));
+    }
+
+    #[test]
+    fn parse_items_860() {
+        let data: &[u8] = &[
+            100, 0, 1, 0, 0, 0, 0, 0, // item, creature, effect and missile counts
+            0, 150, 0, 16, 255, // item 100: ground with speed 150, pickupable
+            1, 1, 1, 1, 1, 1, 1, 5, 0, // 1x1, one layer, one pattern, one phase, sprite 5
+            255, 1, 1, 1, 4, 1, 1, 1, 1, 0, 2, 0, 3, 0, 4, 0, // creature 1: 4 directions, no frame groups
+        ];
+        let things = parse_items(&mut MemReader::new(data), DatOptions::from(ClientVersion(860))).unwrap();
+
+        let item = &things[&ThingCategory::Item][&100];
+        assert_eq!(item.ground_speed(), Some(150));
+        assert!(item.has_attribute(DatAttributesHeader::Pickupable));
+        assert_eq!(item.frame_groups[&FrameGroupType::Idle].sprites, [5]);
+
+        let creature = &things[&ThingCategory::Creature][&1];
+        assert_eq!(creature.pattern_size(), (4, 1, 1));
+        assert_eq!(creature.frame_groups[&FrameGroupType::Idle].sprites, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn parse_items_1000() {
+        let data: &[u8] = &[
+            100, 0, 0, 0, 0, 0, 0, 0, // item, creature, effect and missile counts
+            16, 17, 255, // item 100: no move animation, pickupable
+            1, 1, 1, 1, 1, 1, 1, 5, 0, 0, 0, // 1x1, one layer, one pattern, one phase, sprite 5
+        ];
+        let things = parse_items(&mut MemReader::new(data), DatOptions::from(ClientVersion(1000))).unwrap();
+
+        let item = &things[&ThingCategory::Item][&100];
+        assert!(item.has_attribute(DatAttributesHeader::NoMoveAnimation));
+        assert!(item.has_attribute(DatAttributesHeader::Pickupable));
+        assert_eq!(item.frame_groups[&FrameGroupType::Idle].sprites, [5]);
+    }
 
-    let signature = data.get::<u32>();
-    parse_items(data)
+    #[test]
+    fn parse_items_1050() {
+        let data: &[u8] = &[
+            99, 0, 1, 0, 0, 0, 0, 0, // item, creature, effect and missile counts
+            255, 1, 1, 1, 1, 1, 1, 2, // creature 1: 1x1, one layer, one pattern, two phases
+            0, 0, 0, 0, 0, 0, // animator: async, loop count, start phase
+            100, 0, 0, 0, 100, 0, 0, 0, 200, 0, 0, 0, 200, 0, 0, 0, // phase durations
+            1, 0, 0, 0, 2, 0, 0, 0, // sprites 1 and 2
+        ];
+        let things = parse_items(&mut MemReader::new(data), DatOptions::from(ClientVersion(1050))).unwrap();
+
+        // the frame group count only comes with 10.57
+        let creature = &things[&ThingCategory::Creature][&1];
+        assert_eq!(creature.frame_groups.len(), 1);
+        assert!(creature.frame_groups[&FrameGroupType::Idle].animator.is_some());
+        assert_eq!(creature.frame_groups[&FrameGroupType::Idle].sprites, [1, 2]);
+    }
+
+    #[test]
+    fn parse_items_extended() {
+        let data: &[u8] = &[
+            100, 0, 0, 0, 0, 0, 0, 0, // item, creature, effect and missile counts
+            255, 1, 1, 1, 1, 1, 1, 1, 0x40, 0x42, 0x0F, 0x00, // item 100: sprite 1000000
+        ];
+        let options = DatOptions { version: ClientVersion(860), extended: true };
+        let things = parse_items(&mut MemReader::new(data), options).unwrap();
+        assert_eq!(things[&ThingCategory::Item][&100].frame_groups[&FrameGroupType::Idle].sprites, [1_000_000]);
+    }
+
+    #[test]
+    fn animation_of_a_pattern() {
+        let data: &[u8] = &[
+            100, 0, 0, 0, 0, 0, 0, 0, // item, creature, effect and missile counts
+            255, 1, 1, 1, 2, 1, 1, 2, // item 100: 1x1, one layer, two x patterns, two phases
+            1, 0, 2, 0, 3, 0, 4, 0, // sprites of phase 0 and 1 by x pattern
+        ];
+        let things = parse_items(&mut MemReader::new(data), DatOptions::from(ClientVersion(860))).unwrap();
+
+        let mut sprites = HashMap::new();
+        for id in 1..=4 {
+            sprites.insert(id, ImageBuffer::from_pixel(32, 32, Rgba([id as u8, 0, 0, 255])));
+        }
+        let spr = SpriteData { signature: 0, sprites };
+
+        let animation = things[&ThingCategory::Item][&100].get_animation(&spr, FrameGroupType::Idle, 1, 0, 0);
+        assert_eq!(animation.len(), 2);
+        assert_eq!(animation[0].0.get_pixel(0, 0).data, [2, 0, 0, 255]);
+        assert_eq!(animation[1].0.get_pixel(0, 0).data, [4, 0, 0, 255]);
+        assert_eq!(animation[1].1, 500);
+    }
+
+    #[test]
+    fn animation_durations() {
+        let data: &[u8] = &[
+            100, 0, 0, 0, 0, 0, 0, 0, // item, creature, effect and missile counts
+            255, 1, 1, 1, 1, 1, 1, 2, // item 100: 1x1, one layer, one pattern, two phases
+            0, 0, 0, 0, 0, 0, // animator: async, loop count, start phase
+            100, 0, 0, 0, 200, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // phase durations
+            1, 0, 0, 0, 2, 0, 0, 0, // sprites 1 and 2
+        ];
+        let things = parse_items(&mut MemReader::new(data), DatOptions::from(ClientVersion(1050))).unwrap();
+
+        let mut sprites = HashMap::new();
+        for id in 1..=2 {
+            sprites.insert(id, ImageBuffer::from_pixel(32, 32, Rgba([id as u8, 0, 0, 255])));
+        }
+        let spr = SpriteData { signature: 0, sprites };
+
+        let animation = things[&ThingCategory::Item][&100].get_animation(&spr, FrameGroupType::Idle, 0, 0, 0);
+        let durations = animation.iter().map(|(_, x)| *x).collect::<Vec<_>>();
+        assert_eq!(durations, [150, u32::MAX]);
+    }
+
+    #[test]
+    fn oversized_things() {
+        match parse_items(&mut MemReader::new(&[0xFF, 0xFF, 0, 0, 0, 0, 0, 0]), DatOptions::default()) {
+            Err(Error::Truncated { offset: 8 }) => {},
+            x => panic!("expected truncated data, got {:?}", x.err())
+        }
+
+        // 33x33 patterns do not fit into a texture
+        let mut data = vec![100, 0, 0, 0, 0, 0, 0, 0, 255, 1, 1, 1, 33, 33, 1, 1];
+        data.resize(data.len() + 33 * 33 * 4, 0);
+        let things = parse_items(&mut MemReader::new(&data), DatOptions::default()).unwrap();
+        let item = &things[&ThingCategory::Item][&100];
+        let spr = SpriteData { signature: 0, sprites: HashMap::new() };
+        assert!(item.get_texture(&spr).is_none());
+        assert_eq!(item.frame_groups[&FrameGroupType::Idle].get_sprite(1, 0, 0, 0, 0, 0, 0), None);
+        assert_eq!(item.first_sprites(FrameGroupType::Idle), [0]);
+    }
 }
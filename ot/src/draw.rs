@@ -4,13 +4,18 @@ use point::Point;
 pub type Image = image::RgbaImage;
 
 pub trait Drawable {
-    fn draw(&mut self, x: u32, y: u32, other: &mut Image);
+    fn draw(&mut self, x: i32, y: i32, other: &mut Image);
 }
 
 impl Drawable for Image {
-    fn draw(&mut self, x_offset: u32, y_offset: u32, other: &mut Image) {
+    /// Copies `other` into this image with its top left corner at the offset,
+    /// pixels outside of this image are left out so the offset may be negative.
+    fn draw(&mut self, x_offset: i32, y_offset: i32, other: &mut Image) {
         for (x, y, pixel) in other.enumerate_pixels_mut() {
-            self[(x + x_offset, y + y_offset)] = *pixel;
+            let (x, y) = (x as i64 + x_offset as i64, y as i64 + y_offset as i64);
+            if x >= 0 && y >= 0 && x < self.width() as i64 && y < self.height() as i64 {
+                self[(x as u32, y as u32)] = *pixel;
+            }
         }
     }
 }
@@ -49,17 +54,18 @@ const COLOR_GREEN: Color = Color { r: 0, g: 255, b: 0, a: 255 };
 const COLOR_BLUE: Color = Color { r: 0, g: 0, b: 255, a: 255 };
 const COLOR_YELLOW: Color = Color { r: 255, g: 255, b: 0, a: 255 };
 
-pub const MASK_COLORS: &'static[Color] = &[COLOR_RED, COLOR_GREEN, COLOR_BLUE, COLOR_YELLOW];
+pub const MASK_COLORS: &[Color] = &[COLOR_RED, COLOR_GREEN, COLOR_BLUE, COLOR_YELLOW];
 
 pub trait ImageFunctions {
     fn mask(&mut self, color: &Color);
     fn blit(&mut self, pos: Point, image: &Image);
+    fn blit_opacity(&mut self, pos: Point, image: &Image, opacity: f32);
 }
 
 impl ImageFunctions for Image {
 
     fn mask(&mut self, masked_color: &Color) {
-        for (x, y, pixel) in self.enumerate_pixels_mut() {
+        for pixel in self.pixels_mut() {
             let pixel_color = Color::from_pixel(pixel);
             let mut write_color = if pixel_color == *masked_color {
                 COLOR_WHITE
@@ -71,16 +77,87 @@ impl ImageFunctions for Image {
         }
     }
 
-    // https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/framework/graphics/image.cpp#L100
     fn blit(&mut self, dest: Point, other: &Image) {
-        for (x, y, pixel) in other.enumerate_pixels() {
-            let xx = dest.x as u32 + x;
-            let yy = dest.y as u32 + y;
-            //if pixel.data[3] != 0 { // TODO: figure out why alpha is 0 (spr parse set's it to zero, but why does this work on otclient? what do we miss?)
-                self.put_pixel(xx, yy, *pixel);
-            //}
+        self.blit_opacity(dest, other, 1.0);
+    }
+
+    /// Draws `other` over this image with its top left corner at `dest`, blending
+    /// with source-over alpha compositing. Pixels outside of this image are left
+    /// out, so `dest` may be negative. `opacity` scales the alpha of `other`,
+    /// it is clamped to 0.0..=1.0.
+    fn blit_opacity(&mut self, dest: Point, other: &Image, opacity: f32) {
+        let opacity = opacity.clamp(0.0, 1.0);
+        let left = std::cmp::max(0, -dest.x) as u32;
+        let top = std::cmp::max(0, -dest.y) as u32;
+        let right = std::cmp::min(other.width() as i64, self.width() as i64 - dest.x as i64);
+        let bottom = std::cmp::min(other.height() as i64, self.height() as i64 - dest.y as i64);
+        if right <= 0 || bottom <= 0 {
+            return;
         }
 
+        for y in top..bottom as u32 {
+            for x in left..right as u32 {
+                let source = other.get_pixel(x, y).data;
+                let source_alpha = source[3] as f32 / 255.0 * opacity;
+                if source_alpha <= 0.0 {
+                    continue;
+                }
+
+                let pixel = self.get_pixel_mut((dest.x + x as i32) as u32, (dest.y + y as i32) as u32);
+                let alpha = pixel.data[3] as f32 / 255.0 * (1.0 - source_alpha);
+                let out_alpha = source_alpha + alpha;
+                for (channel, &source_channel) in pixel.data[..3].iter_mut().zip(&source[..3]) {
+                    *channel = ((source_channel as f32 * source_alpha + *channel as f32 * alpha) / out_alpha).round() as u8;
+                }
+                pixel.data[3] = (out_alpha * 255.0).round() as u8;
+            }
+        }
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer};
+
+    #[test]
+    fn blit_clipped_and_blended() {
+        let mut image: Image = ImageBuffer::from_pixel(2, 2, Rgba([0, 0, 255, 255]));
+        let mut other: Image = ImageBuffer::from_pixel(2, 2, Rgba([255, 0, 0, 255]));
+        other.put_pixel(1, 1, Rgba([255, 0, 0, 128]));
+        other.put_pixel(0, 1, Rgba([0, 255, 0, 0]));
+
+        image.blit(Point::new(-1, -1), &other);
+        assert_eq!(image.get_pixel(0, 0).data, [128, 0, 127, 255]);
+        assert_eq!(image.get_pixel(1, 0).data, [0, 0, 255, 255]);
+
+        image.blit(Point::new(1, 0), &other);
+        assert_eq!(image.get_pixel(1, 0).data, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 1).data, [0, 0, 255, 255]);
+
+        let mut empty: Image = ImageBuffer::new(1, 1);
+        empty.blit_opacity(Point::new(0, 0), &other, 0.5);
+        assert_eq!(empty.get_pixel(0, 0).data, [255, 0, 0, 128]);
+
+        let mut empty: Image = ImageBuffer::new(1, 1);
+        empty.blit_opacity(Point::new(0, 0), &other, 2.0);
+        assert_eq!(empty.get_pixel(0, 0).data, [255, 0, 0, 255]);
+        empty.blit_opacity(Point::new(0, 0), &ImageBuffer::from_pixel(1, 1, Rgba([0, 0, 255, 255])), -1.0);
+        assert_eq!(empty.get_pixel(0, 0).data, [255, 0, 0, 255]);
+
+        image.blit(Point::new(2, 0), &other);
+        image.blit(Point::new(-2, 5), &other);
+    }
+
+    #[test]
+    fn draw_clipped() {
+        let mut image: Image = ImageBuffer::from_pixel(2, 2, Rgba([0, 0, 255, 255]));
+        let mut other: Image = ImageBuffer::from_pixel(2, 2, Rgba([255, 0, 0, 128]));
+        image.draw(-1, 1, &mut other);
+        assert_eq!(image.get_pixel(0, 1).data, [255, 0, 0, 128]);
+        assert_eq!(image.get_pixel(1, 1).data, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(0, 0).data, [0, 0, 255, 255]);
+        image.draw(-3, 2, &mut other);
+    }
+}
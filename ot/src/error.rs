@@ -0,0 +1,63 @@
+use std::fmt;
+use std::io;
+
+use image::ImageError;
+
+use version::ClientVersion;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Image(ImageError),
+    BadMagic { magic: u32, offset: usize },
+    UnknownNodeType { node_type: u8, offset: usize },
+    UnexpectedNode { node_type: u8, offset: usize },
+    MissingNode { node_type: u8, offset: usize },
+    UnknownAttribute { attribute: u8, offset: usize },
+    Truncated { offset: usize },
+    SpriteOverflow { offset: usize },
+    PositionOverflow { offset: usize },
+    UnsupportedVersion { version: u32, offset: usize },
+    VersionMismatch { dat: ClientVersion, spr: ClientVersion },
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<ImageError> for Error {
+    fn from(e: ImageError) -> Error {
+        Error::Image(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Image(e) => write!(f, "image error: {}", e),
+            Error::BadMagic { magic, offset } => write!(f, "unexpected magic bytes 0x{:08X} at offset {}", magic, offset),
+            Error::UnknownNodeType { node_type, offset } => write!(f, "unknown node type 0x{:02X} at offset {}", node_type, offset),
+            Error::UnexpectedNode { node_type, offset } => write!(f, "unexpected node type 0x{:02X} at offset {}", node_type, offset),
+            Error::MissingNode { node_type, offset } => write!(f, "missing node type 0x{:02X} in the node at offset {}", node_type, offset),
+            Error::UnknownAttribute { attribute, offset } => write!(f, "unknown attribute 0x{:02X} at offset {}", attribute, offset),
+            Error::Truncated { offset } => write!(f, "unexpected end of data at offset {}", offset),
+            Error::SpriteOverflow { offset } => write!(f, "sprite pixels run past the end of the sprite at offset {}", offset),
+            Error::PositionOverflow { offset } => write!(f, "tile position out of range at offset {}", offset),
+            Error::UnsupportedVersion { version, offset } => write!(f, "unsupported version {} at offset {}", version, offset),
+            Error::VersionMismatch { dat, spr } => write!(f, "dat is for client {} but spr is for client {}", dat.0, spr.0),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
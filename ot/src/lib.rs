@@ -7,16 +7,34 @@ extern crate num_traits;
 extern crate image;
 extern crate rand;
 extern crate rayon;
+extern crate memmap;
+extern crate flate2;
+extern crate gif;
 
+pub mod error;
+pub mod version;
 pub mod mem_read;
+pub mod mem_write;
 pub mod binary_tree;
 pub mod point;
 pub mod size;
+pub mod rect;
 pub mod draw;
 pub mod dat;
 pub mod otb;
 pub mod otbm;
 pub mod spr;
+pub mod render;
+pub mod pyramid;
+pub mod minimap;
+pub mod otmm;
+pub mod animation;
+pub mod outfit;
+pub mod otb_gen;
+mod md5;
+
+pub use error::Error;
+pub use version::detect;
 
 #[macro_export]
 macro_rules! flush {
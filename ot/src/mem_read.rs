@@ -1,48 +1,161 @@
-use std::io::{Error, Read};
-use std::mem::*;
-use std::slice::*;
+use std::fs::File;
+use std::io::{Error as IoError, Write};
 
-pub trait MemType {}
+use memmap::Mmap;
 
-impl MemType for u8 {}
-impl MemType for u16 {}
-impl MemType for u32 {}
-impl MemType for u64 {}
+use error::Error;
 
-impl MemType for i8 {}
-impl MemType for i16 {}
-impl MemType for i32 {}
-impl MemType for i64 {}
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Primitives that can be read from and written to tibia files, always stored little-endian.
+pub trait MemType: sealed::Sealed + Sized {
+    fn read_from<R: MemRead + ?Sized>(data: &mut R) -> Result<Self, Error>;
+    fn write_to<W: Write + ?Sized>(&self, data: &mut W) -> Result<(), IoError>;
+}
+
+macro_rules! mem_type {
+    ($($t:ty),*) => {
+        $(
+            impl sealed::Sealed for $t {}
+
+            impl MemType for $t {
+                fn read_from<R: MemRead + ?Sized>(data: &mut R) -> Result<Self, Error> {
+                    let mut bytes = [0u8; std::mem::size_of::<$t>()];
+                    data.read_bytes(&mut bytes)?;
+                    Ok(<$t>::from_le_bytes(bytes))
+                }
+
+                fn write_to<W: Write + ?Sized>(&self, data: &mut W) -> Result<(), IoError> {
+                    data.write_all(&self.to_le_bytes())
+                }
+            }
+        )*
+    }
+}
+
+mem_type!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl<const N: usize> sealed::Sealed for [u8; N] {}
+
+impl<const N: usize> MemType for [u8; N] {
+    fn read_from<R: MemRead + ?Sized>(data: &mut R) -> Result<Self, Error> {
+        let mut bytes = [0u8; N];
+        data.read_bytes(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write_to<W: Write + ?Sized>(&self, data: &mut W) -> Result<(), IoError> {
+        data.write_all(self)
+    }
+}
 
 pub trait MemRead {
-    fn get<U>(&mut self) -> Result<U, Error>;
-    fn get_str(&mut self) -> Result<String, Error>;
-    fn get_str_sized(&mut self, size: usize) -> Result<String, Error>;
-    fn skip(&mut self, count: u64);
-}
-
-impl<T: Read> MemRead for T {
-    fn get<U>(&mut self) -> Result<U, Error> {
-        unsafe {
-            let mut x: U = uninitialized();
-            let slice = from_raw_parts_mut(&mut x as *mut U as *mut u8, size_of::<U>());
-            self.read_exact(slice).map(|_| x)
-        }
+    /// Fills `buffer` completely or fails with `Error::Truncated` without consuming anything.
+    fn read_bytes(&mut self, buffer: &mut [u8]) -> Result<(), Error>;
+    /// The offset of the next byte in the file.
+    fn position(&self) -> usize;
+
+    fn get<U: MemType>(&mut self) -> Result<U, Error> {
+        U::read_from(self)
     }
 
     fn get_str(&mut self) -> Result<String, Error> {
         let size = self.get::<u16>()?;
-        self::MemRead::get_str_sized(self, size as _)
+        self.get_str_sized(size as _)
     }
 
+    /// Text in the client files is Latin-1.
     fn get_str_sized(&mut self, size: usize) -> Result<String, Error> {
-        let mut buffer: Vec<u8> = Vec::with_capacity(size);
-        self.take(size as u64)
-            .read_to_end(&mut buffer)
-            .map(|_| String::from_utf8_lossy(&buffer).into())
+        let mut buffer = vec![0u8; size];
+        self.read_bytes(&mut buffer)?;
+        Ok(buffer.iter().map(|&x| x as char).collect())
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), Error> {
+        for _ in 0..count {
+            self.get::<u8>()?;
+        }
+        Ok(())
+    }
+}
+
+/// Maps a file into memory instead of reading it, so only the parts that are
+/// actually accessed get loaded.
+pub fn map_file(filename: String) -> Result<Mmap, Error> {
+    let file = File::open(filename)?;
+    // the mapping is only valid as long as nobody truncates the file while we read it
+    Ok(unsafe { Mmap::map(&file)? })
+}
+
+pub struct MemReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    offset: usize,
+}
+
+impl<'a> MemReader<'a> {
+    pub fn new(data: &'a [u8]) -> MemReader<'a> {
+        MemReader::with_offset(data, 0)
+    }
+
+    /// Reads `data` as if it was found at `offset` in a file, positions and errors are reported accordingly.
+    pub fn with_offset(data: &'a [u8], offset: usize) -> MemReader<'a> {
+        MemReader { data, position: 0, offset }
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.position..]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+}
+
+impl<'a> MemRead for MemReader<'a> {
+    fn read_bytes(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let end = self.position + buffer.len();
+        if end > self.data.len() {
+            return Err(Error::Truncated { offset: self.position() });
+        }
+
+        buffer.copy_from_slice(&self.data[self.position..end]);
+        self.position = end;
+        Ok(())
+    }
+
+    fn position(&self) -> usize {
+        self.offset + self.position
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), Error> {
+        if self.position + count > self.data.len() {
+            return Err(Error::Truncated { offset: self.position() });
+        }
+
+        self.position += count;
+        Ok(())
     }
+}
 
-    fn skip(&mut self, count: u64) {
-        std::io::copy(&mut self.take(count), &mut std::io::sink()).expect("could not skip bytes");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_little_endian_and_bounds() {
+        let data = &mut MemReader::with_offset(&[0x01, 0x02, 0x03, 0x04, 0xFF, 0x02, 0x00, b'o', b't'], 10);
+        assert_eq!(data.get::<u32>().unwrap(), 0x0403_0201);
+        assert_eq!(data.get::<i8>().unwrap(), -1);
+        assert_eq!(data.position(), 15);
+        assert_eq!(data.get_str().unwrap(), "ot");
+        assert!(data.is_empty());
+
+        match data.get::<u16>() {
+            Err(Error::Truncated { offset: 19 }) => {},
+            x => panic!("expected truncated data, got {:?}", x)
+        }
     }
 }
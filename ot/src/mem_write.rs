@@ -0,0 +1,29 @@
+use std::io::{Error, ErrorKind, Write};
+
+use mem_read::MemType;
+
+pub trait MemWrite {
+    fn put<U: MemType>(&mut self, value: U) -> Result<(), Error>;
+    fn put_str(&mut self, value: &str) -> Result<(), Error>;
+}
+
+impl<T: Write> MemWrite for T {
+    fn put<U: MemType>(&mut self, value: U) -> Result<(), Error> {
+        value.write_to(self)
+    }
+
+    /// Writes the string as Latin-1 the way the client files store text,
+    /// characters outside of it are rejected.
+    fn put_str(&mut self, value: &str) -> Result<(), Error> {
+        let bytes = value.chars().map(|x| match x as u32 {
+            x @ 0..=0xFF => Ok(x as u8),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "string is not latin-1")),
+        }).collect::<Result<Vec<u8>, Error>>()?;
+        if bytes.len() > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "string is too long"));
+        }
+
+        self.put(bytes.len() as u16)?;
+        self.write_all(&bytes)
+    }
+}
@@ -0,0 +1,96 @@
+// https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/framework/util/color.cpp#L62
+
+use image::{ImageBuffer, Rgba};
+
+use dat::Things;
+use draw::*;
+use otb::OtbItems;
+use otbm::{Map, Tile};
+use rect::Rect;
+use render::stacked_things;
+
+/// Converts an 8 bit minimap color of the 6x6x6 color cube to rgb, colors
+/// outside of the cube are black.
+pub fn minimap_color(color: u8) -> Rgba<u8> {
+    if color >= 216 {
+        return Rgba([0, 0, 0, 255]);
+    }
+
+    let r = color / 36 % 6 * 51;
+    let g = color / 6 % 6 * 51;
+    let b = color % 6 * 51;
+    Rgba([r, g, b, 255])
+}
+
+/// The minimap color of the topmost item on the tile that has one.
+pub fn tile_color(tile: &Tile, dat: &Things, otb: &OtbItems) -> Option<u8> {
+    stacked_things(tile, dat, otb)
+        .iter()
+        .rev()
+        .filter_map(|(_, thing)| thing.minimap_color())
+        .next()
+}
+
+/// Renders floor `z` with one pixel per tile, `rect` is in tile coordinates.
+/// Tiles without a minimap color stay transparent.
+pub fn render_minimap(map: &Map, dat: &Things, otb: &OtbItems, z: u8, rect: Rect) -> Image {
+    let mut image: Image = ImageBuffer::new(rect.width as u32, rect.height as u32);
+    for (position, tile) in &map.tiles {
+        let (x, y) = (position.x as i32 - rect.x, position.y as i32 - rect.y);
+        if position.z != z || x < 0 || y < 0 || x >= rect.width || y >= rect.height {
+            continue;
+        }
+
+        if let Some(color) = tile_color(tile, dat, otb) {
+            image.put_pixel(x as u32, y as u32, minimap_color(color));
+        }
+    }
+    image
+}
+
+/// Renders floor `z` of the whole map, the image covers `Map::bounds` so images
+/// of different floors line up.
+pub fn render_map_minimap(map: &Map, dat: &Things, otb: &OtbItems, z: u8) -> Image {
+    render_minimap(map, dat, otb, z, map.bounds())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dat::{parse_items, DatOptions};
+    use mem_read::MemReader;
+    use otb::{ItemCategory, ItemType};
+    use otbm::{ItemNode, Position};
+
+    #[test]
+    fn minimap_palette() {
+        assert_eq!(minimap_color(0).data, [0, 0, 0, 255]);
+        assert_eq!(minimap_color(215).data, [255, 255, 255, 255]);
+        assert_eq!(minimap_color(24).data, [0, 204, 0, 255]); // grass
+        assert_eq!(minimap_color(186).data, [255, 51, 0, 255]);
+        assert_eq!(minimap_color(255).data, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn color_zero_is_skipped() {
+        let data: &[u8] = &[
+            101, 0, 0, 0, 0, 0, 0, 0, // item, creature, effect and missile counts
+            0, 150, 0, 29, 24, 0, 255, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, // item 100: ground with minimap color 24
+            29, 0, 0, 255, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, // item 101: minimap color 0
+        ];
+        let dat = parse_items(&mut MemReader::new(data), DatOptions::default()).unwrap();
+
+        let mut otb = OtbItems::default();
+        otb.insert(ItemCategory::Ground(ItemType::new(100, 100)));
+        otb.insert(ItemCategory::Invalid(ItemType::new(101, 101)));
+
+        let tile = Tile {
+            position: Position::new(100, 100, 7),
+            house_id: None,
+            flags: 0,
+            items: vec![ItemNode::new(100), ItemNode::new(101)],
+        };
+        assert_eq!(tile_color(&tile, &dat, &otb), Some(24));
+    }
+}
@@ -1,33 +1,36 @@
 #![allow(dead_code)]
 
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::{Error, Read};
-use std::collections::HashMap;
+use std::io::Write;
 
-use image::Primitive;
+use error::Error;
 use mem_read::*;
+use mem_write::*;
 use binary_tree::*;
+use version::ClientVersion;
 
-#[derive(Debug)]
-pub struct Main {
-    signature: u32,
-    children: Vec<MainChild>
-    // parse_child
-}
-
-#[derive(Debug)]
-pub enum MainChild {
-    Root(Root),
-}
+const ROOT_ATTR_VERSION: u8 = 0x01;
+const DESCRIPTION_SIZE: usize = 128;
 
 #[derive(Debug)]
 pub struct Root {
-    otb_major_version: u32,
-    otb_minor_version: u32,
+    version: OtbVersion,
     children: Vec<ItemCategory>
     // parse_child
 }
 
+/// The version node of the file. `minor` is the client the file belongs to,
+/// e.g. 20 for 8.60, `build` counts the edits made to the file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OtbVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub build: u32,
+    /// At most 127 bytes are kept when writing.
+    pub description: String,
+}
+
 #[derive(Debug)]
 pub enum ItemCategory {
     Invalid(ItemType),
@@ -44,7 +47,9 @@ pub enum ItemCategory {
     Splash(ItemType),
     Fluid(ItemType),
     Door(ItemType),
-    Deprecated(ItemType)
+    Deprecated(ItemType),
+    /// A server id the file skips, kept so that every id up to the highest one has an entry.
+    Placeholder(ItemType)
 }
 
 pub trait ItemCategoryTrait {
@@ -68,43 +73,118 @@ impl ItemCategoryTrait for ItemCategory {
              ItemCategory::Splash(val) => val,
              ItemCategory::Fluid(val) => val,
              ItemCategory::Door(val) => val,
-             ItemCategory::Deprecated(val) => val
+             ItemCategory::Deprecated(val) => val,
+             ItemCategory::Placeholder(val) => val
         }
     }
 }
 
-#[derive(Debug)]
-pub struct ItemType {
-    server_id: u16,
-    client_id: u16,
-    name: String
-}
-
-impl HasChildren for Main {
-    type Output = Main;
-    type Child = MainChild;
-
-    fn parse<T: MemRead>(data: &mut T, children: Vec<Self::Child>) -> Result<Self, Error> {
-        //println!("parse main");
-
-        let signature = data.get::<u32>()?;
-        if signature != 0x0 {
-            panic!("main signature wasn't 0x0")
+impl ItemCategory {
+    fn item_type_mut(&mut self) -> &mut ItemType {
+        match self {
+             ItemCategory::Invalid(val) => val,
+             ItemCategory::Ground(val) => val,
+             ItemCategory::Container(val) => val,
+             ItemCategory::Weapon(val) => val,
+             ItemCategory::Ammunition(val) => val,
+             ItemCategory::Armor(val) => val,
+             ItemCategory::Charges(val) => val,
+             ItemCategory::Teleport(val) => val,
+             ItemCategory::MagicField(val) => val,
+             ItemCategory::Writable(val) => val,
+             ItemCategory::Key(val) => val,
+             ItemCategory::Splash(val) => val,
+             ItemCategory::Fluid(val) => val,
+             ItemCategory::Door(val) => val,
+             ItemCategory::Deprecated(val) => val,
+             ItemCategory::Placeholder(val) => val
         }
+    }
 
-        Ok(Main { signature, children })
+    pub fn is_placeholder(&self) -> bool {
+        matches!(self, ItemCategory::Placeholder(_))
     }
 
-    fn parse_child<T: MemRead>(data: &mut T) -> Result<Self::Child, Error> {
-        //println!("parse main child");
-        let identifier = data.get::<u8>()?;
-        match identifier {
-            0x00 => Ok(MainChild::Root(Root::read_node(data)?)),
-            _ => panic!("unknown type info")
-        }
+    /// The node type the item is stored with, None for placeholders which are not stored.
+    fn node_type(&self) -> Option<u8> {
+        Some(match self {
+             ItemCategory::Invalid(_) => 0,
+             ItemCategory::Ground(_) => 1,
+             ItemCategory::Container(_) => 2,
+             ItemCategory::Weapon(_) => 3,
+             ItemCategory::Ammunition(_) => 4,
+             ItemCategory::Armor(_) => 5,
+             ItemCategory::Charges(_) => 6,
+             ItemCategory::Teleport(_) => 7,
+             ItemCategory::MagicField(_) => 8,
+             ItemCategory::Writable(_) => 9,
+             ItemCategory::Key(_) => 10,
+             ItemCategory::Splash(_) => 11,
+             ItemCategory::Fluid(_) => 12,
+             ItemCategory::Door(_) => 13,
+             ItemCategory::Deprecated(_) => 14,
+             ItemCategory::Placeholder(_) => return None
+        })
     }
 }
 
+// flags and attributes as defined in itemloader.h of the opentibia server, which ItemEditor writes
+pub const FLAG_BLOCK_SOLID: u32 = 1 << 0;
+pub const FLAG_BLOCK_PROJECTILE: u32 = 1 << 1;
+pub const FLAG_BLOCK_PATHFIND: u32 = 1 << 2;
+pub const FLAG_HAS_HEIGHT: u32 = 1 << 3;
+pub const FLAG_USEABLE: u32 = 1 << 4;
+pub const FLAG_PICKUPABLE: u32 = 1 << 5;
+pub const FLAG_MOVEABLE: u32 = 1 << 6;
+pub const FLAG_STACKABLE: u32 = 1 << 7;
+pub const FLAG_FLOOR_CHANGE_DOWN: u32 = 1 << 8;
+pub const FLAG_FLOOR_CHANGE_NORTH: u32 = 1 << 9;
+pub const FLAG_FLOOR_CHANGE_EAST: u32 = 1 << 10;
+pub const FLAG_FLOOR_CHANGE_SOUTH: u32 = 1 << 11;
+pub const FLAG_FLOOR_CHANGE_WEST: u32 = 1 << 12;
+pub const FLAG_ALWAYS_ON_TOP: u32 = 1 << 13;
+pub const FLAG_READABLE: u32 = 1 << 14;
+pub const FLAG_ROTATABLE: u32 = 1 << 15;
+pub const FLAG_HANGABLE: u32 = 1 << 16;
+pub const FLAG_VERTICAL: u32 = 1 << 17;
+pub const FLAG_HORIZONTAL: u32 = 1 << 18;
+pub const FLAG_CANNOT_DECAY: u32 = 1 << 19;
+pub const FLAG_ALLOW_DISTREAD: u32 = 1 << 20;
+pub const FLAG_CLIENT_CHARGES: u32 = 1 << 22;
+pub const FLAG_LOOKTHROUGH: u32 = 1 << 23;
+pub const FLAG_ANIMATION: u32 = 1 << 24;
+pub const FLAG_FULL_TILE: u32 = 1 << 25;
+pub const FLAG_FORCE_USE: u32 = 1 << 26;
+
+const ATTR_SERVER_ID: u8 = 0x10;
+const ATTR_CLIENT_ID: u8 = 0x11;
+const ATTR_NAME: u8 = 0x12;
+const ATTR_SPEED: u8 = 0x14;
+const ATTR_SPRITE_HASH: u8 = 0x20;
+const ATTR_MINIMAP_COLOR: u8 = 0x21;
+const ATTR_MAX_READ_WRITE_CHARS: u8 = 0x22;
+const ATTR_MAX_READ_CHARS: u8 = 0x23;
+const ATTR_LIGHT: u8 = 0x2A;
+const ATTR_TOP_ORDER: u8 = 0x2B;
+const ATTR_WARE_ID: u8 = 0x2D;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ItemType {
+    pub(crate) server_id: u16,
+    pub(crate) client_id: u16,
+    pub(crate) flags: u32,
+    pub(crate) name: String,
+    pub(crate) speed: u16,
+    pub(crate) light_level: u16,
+    pub(crate) light_color: u16,
+    pub(crate) top_order: u8,
+    pub(crate) sprite_hash: Option<[u8; 16]>,
+    pub(crate) minimap_color: u16,
+    pub(crate) ware_id: u16,
+    pub(crate) max_read_write_chars: u16,
+    pub(crate) max_read_chars: u16,
+}
+
 impl HasChildren for Root {
     type Output = Root;
     type Child = ItemCategory;
@@ -112,69 +192,63 @@ impl HasChildren for Root {
     fn parse<T: MemRead>(data: &mut T, children: Vec<Self::Child>) -> Result<Self, Error> {
         //println!("parse root");
 
-        let signature = data.get::<u32>()?;
-        if signature != 0x0 {
-            panic!("root signature wasn't 0x0");
-        }
+        data.get::<u32>()?; // flags, unused
 
         let root_attr = data.get::<u8>()?;
-        if root_attr != 0x01 {
-            panic!("invalid root attr");
-        }
-
         let size = data.get::<u16>()?;
-        if size != 4 + 4 + 4 + 128 {
-            panic!("invalid root attr version size");
+        if root_attr != ROOT_ATTR_VERSION || size as usize != 4 + 4 + 4 + DESCRIPTION_SIZE {
+            return Err(Error::UnknownAttribute { attribute: root_attr, offset: data.position() - 3 });
         }
 
-        let otb_major_version = data.get::<u32>()?;
-        let otb_minor_version = data.get::<u32>()?;
-        data.get::<u32>()?; // build number
-        //data.get::<[u8; 128]>()?; // description
-        data.get::<u64>()?;
-        data.get::<u64>()?;
+        let major = data.get::<u32>()?;
+        let minor = data.get::<u32>()?;
+        let build = data.get::<u32>()?;
+        let description = data.get::<[u8; DESCRIPTION_SIZE]>()?;
+        let length = description.iter().position(|&x| x == 0).unwrap_or(DESCRIPTION_SIZE);
+        let description = String::from_utf8_lossy(&description[..length]).into();
 
-        Ok(Root { otb_major_version, otb_minor_version, children })
+        Ok(Root { version: OtbVersion { major, minor, build, description }, children })
     }
 
-    fn parse_child<T: MemRead>(data: &mut T) -> Result<Self::Child, Error> {
+    fn parse_child<'a>(node: RawNode<'a>, _tree: &mut TreeReader<'a>) -> Result<Self::Child, Error> {
         //println!("parse root children");
 
-        let item_category = data.get::<u8>()?;
+        let data = &mut node.props();
+        let item_category = node.node_type();
         //println!("item_category: {}", item_category);
+        let item_type = ItemType::read(data)?;
         Ok(match item_category {
-            0 => ItemCategory::Invalid(ItemType::new(data)?),
-            1 => ItemCategory::Ground(ItemType::new(data)?),
-            2 => ItemCategory::Container(ItemType::new(data)?),
-            3 => ItemCategory::Weapon(ItemType::new(data)?),
-            4 => ItemCategory::Ammunition(ItemType::new(data)?),
-            5 => ItemCategory::Armor(ItemType::new(data)?),
-            6 => ItemCategory::Charges(ItemType::new(data)?),
-            7 => ItemCategory::Teleport(ItemType::new(data)?),
-            8 => ItemCategory::MagicField(ItemType::new(data)?),
-            9 => ItemCategory::Writable(ItemType::new(data)?),
-            10 => ItemCategory::Key(ItemType::new(data)?),
-            11 => ItemCategory::Splash(ItemType::new(data)?),
-            12 => ItemCategory::Fluid(ItemType::new(data)?),
-            13 => ItemCategory::Door(ItemType::new(data)?),
-            14 => ItemCategory::Deprecated(ItemType::new(data)?),
-            _ => panic!("unknown item category {}", item_category)
+            0 => ItemCategory::Invalid(item_type),
+            1 => ItemCategory::Ground(item_type),
+            2 => ItemCategory::Container(item_type),
+            3 => ItemCategory::Weapon(item_type),
+            4 => ItemCategory::Ammunition(item_type),
+            5 => ItemCategory::Armor(item_type),
+            6 => ItemCategory::Charges(item_type),
+            7 => ItemCategory::Teleport(item_type),
+            8 => ItemCategory::MagicField(item_type),
+            9 => ItemCategory::Writable(item_type),
+            10 => ItemCategory::Key(item_type),
+            11 => ItemCategory::Splash(item_type),
+            12 => ItemCategory::Fluid(item_type),
+            13 => ItemCategory::Door(item_type),
+            14 => ItemCategory::Deprecated(item_type),
+            _ => return Err(Error::UnknownNodeType { node_type: item_category, offset: node.offset() })
         })
     }
 }
 
 impl ItemType {
-    fn new<T: MemRead>(data: &mut T) -> Result<ItemType, Error> {
+    pub fn new(server_id: u16, client_id: u16) -> ItemType {
+        ItemType { server_id, client_id, ..ItemType::default() }
+    }
+
+    fn read<T: MemRead>(data: &mut T) -> Result<ItemType, Error> {
         //println!("parse item type");
 
-        let mut item_type = ItemType { server_id: 0, client_id: 0, name: "".to_string() };
-        data.get::<u32>()?; // skip flags
-        static mut LAST_ID: u16 = 99;
+        let mut item_type = ItemType { flags: data.get()?, ..ItemType::default() };
         loop {
-            let attr = match data.get::<u8>() {
-                Ok(attr) => attr,
-                Err(_) => 0x0
-            };
+            let attr = data.get::<u8>().unwrap_or_default();
 
             if attr == 0x0 || attr == 0xFF {
                 return Ok(item_type)
@@ -182,70 +256,460 @@ impl ItemType {
 
             let len = data.get::<u16>()?;
             //println!("attr: {} len: {}", attr, len);
+            let offset = data.position();
+            let mut bytes = vec![0u8; len as usize];
+            data.read_bytes(&mut bytes)?;
+
+            // every attribute has to fill exactly the length it claims
+            let value = &mut MemReader::with_offset(&bytes, offset);
             match attr {
-                16 /* ItemTypeAttrServerId */ => {
-                    let mut server_id = data.get::<u16>()?;
-                    unsafe {
-                        if server_id > 30000 && server_id < 30100 {
-                            server_id -= 30000;
-                        } else if LAST_ID > 99 && LAST_ID != server_id - 1 {
-                            while LAST_ID != server_id -1 {
-                                // TODO: add item types https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/client/itemtype.cpp#L69
-                                LAST_ID += 1;
-                            }
-                        }
-                        LAST_ID = server_id;
-                    }
-                    item_type.server_id = server_id;
+                ATTR_SERVER_ID => item_type.server_id = value.get()?,
+                ATTR_CLIENT_ID => item_type.client_id = value.get()?,
+                ATTR_NAME => item_type.name = value.get_str_sized(len as _)?,
+                ATTR_SPEED => item_type.speed = value.get()?,
+                ATTR_SPRITE_HASH => item_type.sprite_hash = Some(value.get()?),
+                ATTR_MINIMAP_COLOR => item_type.minimap_color = value.get()?,
+                ATTR_MAX_READ_WRITE_CHARS => item_type.max_read_write_chars = value.get()?,
+                ATTR_MAX_READ_CHARS => item_type.max_read_chars = value.get()?,
+                ATTR_LIGHT => {
+                    item_type.light_level = value.get()?;
+                    item_type.light_color = value.get()?;
                 }
-                17 /*ItemTypeAttrClientId*/ => item_type.client_id = data.get::<u16>()?,
-                18 /*ItemTypeAttrName DEPRECATED?*/ => data.skip(len as _), //item_type.name = data.get_str(len as _)?,
-                _ => data.skip(len as _) // skip irrelevant attributes
+                ATTR_TOP_ORDER => item_type.top_order = value.get()?,
+                ATTR_WARE_ID => item_type.ware_id = value.get()?,
+                _ => continue // skip irrelevant attributes
+            }
+
+            if !value.is_empty() {
+                return Err(Error::UnknownAttribute { attribute: attr, offset: offset - 3 });
             }
         }
     }
+
+    fn props(&self) -> Result<Vec<u8>, Error> {
+        let mut props: Vec<u8> = Vec::new();
+        props.put(self.flags)?;
+
+        let mut put_u16 = |attr: u8, value: u16| -> Result<(), Error> {
+            if value != 0 || attr == ATTR_SERVER_ID || attr == ATTR_CLIENT_ID {
+                props.put(attr)?;
+                props.put(2u16)?;
+                props.put(value)?;
+            }
+            Ok(())
+        };
+        put_u16(ATTR_SERVER_ID, self.server_id)?;
+        put_u16(ATTR_CLIENT_ID, self.client_id)?;
+        put_u16(ATTR_SPEED, self.speed)?;
+        put_u16(ATTR_MINIMAP_COLOR, self.minimap_color)?;
+        put_u16(ATTR_MAX_READ_WRITE_CHARS, self.max_read_write_chars)?;
+        put_u16(ATTR_MAX_READ_CHARS, self.max_read_chars)?;
+        put_u16(ATTR_WARE_ID, self.ware_id)?;
+
+        if !self.name.is_empty() {
+            props.put(ATTR_NAME)?;
+            props.put_str(&self.name)?;
+        }
+        if let Some(sprite_hash) = self.sprite_hash {
+            props.put(ATTR_SPRITE_HASH)?;
+            props.put(16u16)?;
+            props.write_all(&sprite_hash)?;
+        }
+        if self.light_level != 0 || self.light_color != 0 {
+            props.put(ATTR_LIGHT)?;
+            props.put(4u16)?;
+            props.put(self.light_level)?;
+            props.put(self.light_color)?;
+        }
+        if self.top_order != 0 {
+            props.put(ATTR_TOP_ORDER)?;
+            props.put(1u16)?;
+            props.put(self.top_order)?;
+        }
+        Ok(props)
+    }
+
+    pub fn server_id(&self) -> u16 {
+        self.server_id
+    }
+
+    /// The id of the dat item the item is drawn with.
+    pub fn client_id(&self) -> u16 {
+        self.client_id
+    }
+
+    /// All `FLAG_*` bits of the item.
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        self.flags = flags;
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Ground speed, 0 for items that are no ground.
+    pub fn speed(&self) -> u16 {
+        self.speed
+    }
+
+    pub fn light_level(&self) -> u16 {
+        self.light_level
+    }
+
+    pub fn light_color(&self) -> u16 {
+        self.light_color
+    }
+
+    /// Order of items that are always on top, 1 is borders, 2 is doors and walls, 3 is hangables.
+    pub fn top_order(&self) -> u8 {
+        self.top_order
+    }
+
+    /// Md5 of the sprites of the dat item, used by item editors to find it again when client ids change.
+    pub fn sprite_hash(&self) -> Option<&[u8; 16]> {
+        self.sprite_hash.as_ref()
+    }
+
+    pub fn minimap_color(&self) -> u16 {
+        self.minimap_color
+    }
+
+    /// The server id of the item this item is traded as in the market.
+    pub fn ware_id(&self) -> u16 {
+        self.ware_id
+    }
+
+    pub fn max_read_write_chars(&self) -> u16 {
+        self.max_read_write_chars
+    }
+
+    pub fn max_read_chars(&self) -> u16 {
+        self.max_read_chars
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct OtbItems {
-    sid_map: HashMap<u16, ItemCategory>,
-    cid_map: HashMap<u16, *const ItemCategory>
+    pub version: OtbVersion,
+    /// The client the items are for, it picks how server ids below 100 are stored.
+    pub client_version: ClientVersion,
+    /// Sorted by server id.
+    items: Vec<ItemCategory>,
+    sid_index: HashMap<u16, usize>,
+    cid_index: HashMap<u16, usize>,
 }
 
 impl OtbItems {
-    fn get_sid(&mut self, sid: u16) -> &ItemCategory {
-        self.sid_map.get(&sid).expect("thing type with sid not found")
+    // https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/client/itemtype.cpp#L69
+    /// Takes the items in file order. Server ids 30001 to 30099, or 20001 to
+    /// 20099 before 9.60, stand for the ids below 100, every id skipped after
+    /// an item gets a placeholder.
+    fn new(version: OtbVersion, client_version: ClientVersion, items: Vec<ItemCategory>) -> OtbItems {
+        let offset = low_id_offset(client_version);
+        let mut sid_map = BTreeMap::new();
+        let mut last_id = 99;
+        for mut item in items {
+            let mut server_id = item.item_type().server_id;
+            if server_id > offset && server_id < offset + 100 {
+                server_id -= offset;
+                item.item_type_mut().server_id = server_id;
+            } else {
+                if last_id > 99 {
+                    for id in last_id + 1..server_id {
+                        sid_map.entry(id).or_insert_with(|| ItemCategory::Placeholder(ItemType { server_id: id, ..ItemType::default() }));
+                    }
+                }
+                last_id = server_id;
+            }
+            sid_map.insert(server_id, item);
+        }
+
+        let mut otb_items = OtbItems { version, client_version, items: sid_map.into_values().collect(), ..OtbItems::default() };
+        otb_items.index();
+        otb_items
     }
 
-    fn get_cid(&mut self, cid: u16) -> &ItemCategory {
-        unsafe {
-            &**(self.cid_map.get(&cid).expect("thing type with cid not found"))
+    fn index(&mut self) {
+        self.sid_index = self.items.iter().enumerate().map(|(i, x)| (x.item_type().server_id, i)).collect();
+        self.cid_index = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| !x.is_placeholder())
+            .map(|(i, x)| (x.item_type().client_id, i))
+            .collect();
+    }
+
+    /// Adds the item, replacing the one with the same server id.
+    pub fn insert(&mut self, item: ItemCategory) {
+        let server_id = item.item_type().server_id;
+        match self.items.binary_search_by_key(&server_id, |x| x.item_type().server_id) {
+            Ok(i) => self.items[i] = item,
+            // new items usually get the next free id, there is no need to index everything again
+            Err(i) if i == self.items.len() => {
+                self.sid_index.insert(server_id, i);
+                if !item.is_placeholder() {
+                    self.cid_index.insert(item.item_type().client_id, i);
+                }
+                self.items.push(item);
+                return;
+            }
+            Err(i) => self.items.insert(i, item),
         }
+        self.index();
+    }
+
+    pub fn get_by_server_id(&self, sid: u16) -> Option<&ItemType> {
+        self.sid_index.get(&sid).map(|&i| self.items[i].item_type())
+    }
+
+    /// Placeholders are never found by their client id. If several items share
+    /// a client id, the one with the highest server id is returned.
+    pub fn get_by_client_id(&self, cid: u16) -> Option<&ItemType> {
+        self.cid_index.get(&cid).map(|&i| self.items[i].item_type())
+    }
+
+    /// The client id the item with the given server id is drawn with.
+    pub fn get_client_id(&self, sid: u16) -> Option<u16> {
+        self.sid_index.get(&sid).map(|&i| &self.items[i]).filter(|x| !x.is_placeholder()).map(|x| x.item_type().client_id)
+    }
+
+    /// All items including placeholders, ordered by server id.
+    pub fn iter(&self) -> std::slice::Iter<'_, ItemCategory> {
+        self.items.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a OtbItems {
+    type Item = &'a ItemCategory;
+    type IntoIter = std::slice::Iter<'a, ItemCategory>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+/// What is added to server ids below 100 in the file.
+fn low_id_offset(version: ClientVersion) -> u16 {
+    if version < ClientVersion(960) {
+        20000
+    } else {
+        30000
     }
 }
 
 pub fn parse(filename: String) -> Result<OtbItems, Error> {
-    let mut file = File::open(filename)?;
+    parse_with_version(filename, ClientVersion::LATEST)
+}
+
+pub fn parse_with_version(filename: String, version: ClientVersion) -> Result<OtbItems, Error> {
+    parse_otb(&map_file(filename)?, version)
+}
+
+pub fn parse_otb(data: &[u8], version: ClientVersion) -> Result<OtbItems, Error> {
+    let mut data = MemReader::new(data);
+
+    let signature = data.get::<u32>()?;
+    if signature != 0x0 {
+        return Err(Error::BadMagic { magic: signature, offset: 0 });
+    }
+
+    let root = Root::read_node(&mut TreeReader::new(data))?;
+    //println!("{:?}", root);
+
+    Ok(OtbItems::new(root.version, version, root.children))
+}
+
+pub fn write(filename: String, otb_items: &OtbItems) -> Result<(), Error> {
     let mut data: Vec<u8> = Vec::new();
-    file.read_to_end(&mut data)?;
-    let data: &mut &[u8] = &mut data.as_ref();
-
-    let mut main = Main::read_node(data)?;
-    //println!("{:?}", main);
-    let root = match main.children.pop() {
-        Some(x) => match x {
-            MainChild::Root(a) => a
-        },
-        _ => panic!("root element not found")
-    };
-
-    let sid_map = root.children.into_iter().map(|x| (x.item_type().server_id, x)).collect::<HashMap<_,_>>();
-    let cid_map = (&sid_map).values().map(|v| (v.item_type().client_id, v as *const _)).collect::<HashMap<_,_>>();
-    let mut otb_items = OtbItems { sid_map, cid_map };
-
-    // crystal coin
-    assert_eq!(otb_items.get_sid(2160).item_type().client_id, 3043);
-    assert_eq!(otb_items.get_cid(3043).item_type().server_id, 2160);
-
-    Ok(otb_items)
-}
\ No newline at end of file
+    write_otb(&mut data, otb_items)?;
+    File::create(filename)?.write_all(&data)?;
+    Ok(())
+}
+
+/// Writes every item except placeholders, attributes with a value of 0 are left
+/// out. Server ids below 100 are moved up again the way `parse_otb` expects them.
+pub fn write_otb<T: Write>(data: &mut T, otb_items: &OtbItems) -> Result<(), Error> {
+    data.put(0u32)?; // signature
+
+    let version = &otb_items.version;
+    let mut description = [0u8; DESCRIPTION_SIZE];
+    let length = std::cmp::min(version.description.len(), DESCRIPTION_SIZE - 1);
+    description[..length].copy_from_slice(&version.description.as_bytes()[..length]);
+
+    let mut props: Vec<u8> = Vec::new();
+    props.put(0u32)?; // flags
+    props.put(ROOT_ATTR_VERSION)?;
+    props.put((4 + 4 + 4 + DESCRIPTION_SIZE) as u16)?;
+    props.put(version.major)?;
+    props.put(version.minor)?;
+    props.put(version.build)?;
+    props.write_all(&description)?;
+    write_node_start(data, 0, &props)?;
+
+    let offset = low_id_offset(otb_items.client_version);
+    for item in otb_items {
+        if let Some(node_type) = item.node_type() {
+            let props = match item.item_type() {
+                x if x.server_id < 100 => ItemType { server_id: x.server_id + offset, ..x.clone() }.props()?,
+                x => x.props()?,
+            };
+            write_node_start(data, node_type, &props)?;
+            write_node_end(data)?;
+        }
+    }
+
+    write_node_end(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn item_type_attributes() {
+        let data: &[u8] = &[
+            0x61, 0x00, 0x00, 0x00, // block solid, pickupable and moveable
+            0x10, 0x02, 0x00, 0x70, 0x08, // server id 2160
+            0x11, 0x02, 0x00, 0xE3, 0x0B, // client id 3043
+            0x12, 0x0C, 0x00, b'c', b'r', b'y', b's', b't', b'a', b'l', b' ', b'c', b'o', b'i', b'n',
+            0x14, 0x02, 0x00, 0x96, 0x00, // speed 150
+            0x2A, 0x04, 0x00, 0x03, 0x00, 0xD7, 0x00, // light level 3, color 215
+            0x2B, 0x01, 0x00, 0x02, // top order 2
+            0x20, 0x10, 0x00, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+            0x1F, 0x01, 0x00, 0x00, // unknown attribute
+        ];
+        let item_type = ItemType::read(&mut MemReader::new(data)).unwrap();
+        assert_eq!(item_type.server_id(), 2160);
+        assert_eq!(item_type.client_id(), 3043);
+        assert_eq!(item_type.name(), "crystal coin");
+        assert!(item_type.has_flag(FLAG_PICKUPABLE));
+        assert!(item_type.has_flag(FLAG_BLOCK_SOLID));
+        assert!(!item_type.has_flag(FLAG_STACKABLE));
+        assert_eq!(item_type.speed(), 150);
+        assert_eq!((item_type.light_level(), item_type.light_color()), (3, 215));
+        assert_eq!(item_type.top_order(), 2);
+        assert_eq!(item_type.sprite_hash().unwrap()[15], 16);
+        assert_eq!(item_type.ware_id(), 0);
+    }
+
+    #[test]
+    fn item_type_attribute_length() {
+        let data: &[u8] = &[
+            0x00, 0x00, 0x00, 0x00,
+            0x10, 0x04, 0x00, 0x70, 0x08, 0x00, 0x00, // server id with 4 bytes
+        ];
+        match ItemType::read(&mut MemReader::new(data)) {
+            Err(Error::UnknownAttribute { attribute: 0x10, offset: 4 }) => {},
+            x => panic!("expected an unknown attribute, got {:?}", x)
+        }
+
+        let data: &[u8] = &[
+            0x00, 0x00, 0x00, 0x00,
+            0x14, 0x01, 0x00, 0x96, // speed with 1 byte
+            0x10, 0x02, 0x00, 0x70, 0x08,
+        ];
+        match ItemType::read(&mut MemReader::new(data)) {
+            Err(Error::Truncated { offset: 7 }) => {},
+            x => panic!("expected a truncated attribute, got {:?}", x)
+        }
+    }
+
+    #[test]
+    fn id_gaps_and_remapping() {
+        let item = |server_id, client_id| ItemCategory::Ground(ItemType { server_id, client_id, ..ItemType::default() });
+        let items = || vec![item(100, 102), item(103, 105), item(30005, 7), item(104, 106)];
+
+        let otb = OtbItems::new(OtbVersion::default(), ClientVersion::LATEST, items());
+        assert_eq!(otb.len(), 6);
+        assert_eq!(otb.get_client_id(100), Some(102));
+        assert!(otb.iter().nth(2).unwrap().is_placeholder());
+        assert_eq!(otb.get_by_server_id(102).map(|x| x.client_id()), Some(0));
+        assert_eq!(otb.get_client_id(102), None);
+        assert_eq!(otb.get_client_id(103), Some(105));
+        assert_eq!(otb.get_client_id(5), Some(7));
+        assert_eq!(otb.get_by_client_id(106).map(|x| x.server_id()), Some(104));
+        assert!(otb.get_by_client_id(0).is_none());
+        assert!(otb.get_by_server_id(99).is_none());
+
+        let ids = otb.iter().map(|x| x.item_type().server_id()).collect::<Vec<_>>();
+        assert_eq!(ids, [5, 100, 101, 102, 103, 104]);
+
+        // nothing is carried over from one file to the next
+        assert_eq!(OtbItems::new(OtbVersion::default(), ClientVersion::LATEST, items()).len(), 6);
+
+        // a low id in between does not hide the gap after it
+        let otb = OtbItems::new(OtbVersion::default(), ClientVersion::LATEST, vec![item(100, 102), item(103, 105), item(30005, 7), item(110, 112)]);
+        let ids = otb.iter().map(|x| x.item_type().server_id()).collect::<Vec<_>>();
+        assert_eq!(ids, [5, 100, 101, 102, 103, 104, 105, 106, 107, 108, 109, 110]);
+
+        // older clients move them by 20000
+        let otb = OtbItems::new(OtbVersion::default(), ClientVersion(860), vec![item(100, 102), item(20005, 7)]);
+        assert_eq!(otb.get_client_id(5), Some(7));
+        assert!(otb.get_by_server_id(20005).is_none());
+    }
+
+    #[test]
+    fn write_parse_round_trip() {
+        let version = OtbVersion { major: 3, minor: 20, build: 42, description: "OTB 3.20.42-8.60".to_string() };
+        let mut coin = ItemType::new(2160, 3043);
+        coin.set_flags(FLAG_PICKUPABLE | FLAG_MOVEABLE | FLAG_STACKABLE);
+        coin.set_name("crystal coin".to_string());
+        coin.sprite_hash = Some([0xFE; 16]);
+        let mut torch = ItemType::new(2050, 2925);
+        torch.light_level = 7;
+        torch.light_color = 206;
+        let mut water = ItemType::new(5, 0xFF);
+        water.top_order = 1;
+
+        let mut otb = OtbItems::new(version.clone(), ClientVersion::LATEST, vec![ItemCategory::Splash(water.clone()), ItemCategory::Deprecated(torch.clone())]);
+        otb.insert(ItemCategory::Ground(coin.clone()));
+        otb.insert(ItemCategory::Charges(coin.clone()));
+        otb.insert(ItemCategory::Placeholder(ItemType::new(2051, 0)));
+
+        let mut written: Vec<u8> = Vec::new();
+        write_otb(&mut written, &otb).unwrap();
+
+        let mut data = MemReader::new(&written);
+        data.get::<u32>().unwrap();
+        let root = Root::read_node(&mut TreeReader::new(data)).unwrap();
+        assert_eq!(root.children.len(), 3);
+        assert_eq!(root.children[0].item_type().server_id(), 30005);
+
+        // the gaps are filled again, the placeholder is not written
+        let otb = parse_otb(&written, ClientVersion::LATEST).unwrap();
+        assert_eq!(otb.version, version);
+        assert_eq!(otb.len(), 3 + 2160 - 2050 - 1);
+        assert_eq!(otb.get_by_server_id(5), Some(&water));
+        assert_eq!(otb.get_by_server_id(2050), Some(&torch));
+        assert_eq!(otb.get_by_client_id(3043), Some(&coin));
+        match otb.iter().last() {
+            Some(ItemCategory::Charges(_)) => {},
+            x => panic!("expected the replaced coin, got {:?}", x)
+        }
+
+        // the flags of the root node are not checked
+        written[6] = 1;
+        assert_eq!(parse_otb(&written, ClientVersion::LATEST).unwrap().len(), otb.len());
+    }
+}
@@ -0,0 +1,248 @@
+// keeps items.otb in line with the dat of a new client, the otb flags of new
+// items are derived from their dat attributes
+
+use dat::{DatAttributes, DatAttributesHeader, FrameGroupType, Thing, ThingCategory, Things};
+use md5::md5;
+use otb::*;
+use spr::SpriteData;
+
+/// Lens help of items that can be read.
+const LENS_HELP_READABLE: u16 = 1112;
+
+/// The gray ItemEditor fills transparent pixels with before hashing.
+const TRANSPARENT_COLOR: u8 = 0x11;
+
+const FLAG_ATTRIBUTES: &[(DatAttributesHeader, u32)] = &[
+    (DatAttributesHeader::NotWalkable, FLAG_BLOCK_SOLID),
+    (DatAttributesHeader::BlockProjectile, FLAG_BLOCK_PROJECTILE),
+    (DatAttributesHeader::NotPathable, FLAG_BLOCK_PATHFIND),
+    (DatAttributesHeader::Elevation, FLAG_HAS_HEIGHT),
+    (DatAttributesHeader::MultiUse, FLAG_USEABLE),
+    (DatAttributesHeader::Pickupable, FLAG_PICKUPABLE),
+    (DatAttributesHeader::Stackable, FLAG_STACKABLE),
+    (DatAttributesHeader::GroundBorder, FLAG_ALWAYS_ON_TOP),
+    (DatAttributesHeader::OnBottom, FLAG_ALWAYS_ON_TOP),
+    (DatAttributesHeader::OnTop, FLAG_ALWAYS_ON_TOP),
+    (DatAttributesHeader::Writeable, FLAG_READABLE),
+    (DatAttributesHeader::WriteableOnce, FLAG_READABLE),
+    (DatAttributesHeader::Rotateable, FLAG_ROTATABLE),
+    (DatAttributesHeader::Hangable, FLAG_HANGABLE),
+    (DatAttributesHeader::HookSouth, FLAG_VERTICAL),
+    (DatAttributesHeader::HookEast, FLAG_HORIZONTAL),
+    (DatAttributesHeader::Look, FLAG_LOOKTHROUGH),
+    (DatAttributesHeader::FullGround, FLAG_FULL_TILE),
+    (DatAttributesHeader::ForceUse, FLAG_FORCE_USE),
+];
+
+/// The flags `item_flags` derives, all others can only be set on the server side.
+pub const DAT_FLAGS: u32 = FLAG_BLOCK_SOLID | FLAG_BLOCK_PROJECTILE | FLAG_BLOCK_PATHFIND | FLAG_HAS_HEIGHT
+    | FLAG_USEABLE | FLAG_PICKUPABLE | FLAG_MOVEABLE | FLAG_STACKABLE | FLAG_ALWAYS_ON_TOP | FLAG_READABLE
+    | FLAG_ROTATABLE | FLAG_HANGABLE | FLAG_VERTICAL | FLAG_HORIZONTAL | FLAG_LOOKTHROUGH | FLAG_ANIMATION
+    | FLAG_FULL_TILE | FLAG_FORCE_USE;
+
+/// An item of the otb whose flags differ from the ones derived from its dat item.
+#[derive(Debug, PartialEq)]
+pub struct FlagDrift {
+    pub server_id: u16,
+    pub client_id: u16,
+    /// Only the bits of `DAT_FLAGS`.
+    pub otb_flags: u32,
+    pub dat_flags: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct UpdateReport {
+    /// Server ids of the new items.
+    pub added: Vec<u16>,
+    pub drifted: Vec<FlagDrift>,
+}
+
+/// The otb flags that follow from the attributes of a dat item.
+pub fn item_flags(thing: &Thing) -> u32 {
+    let mut flags = FLAG_ATTRIBUTES
+        .iter()
+        .filter(|(header, _)| thing.has_attribute(*header))
+        .fold(0, |flags, (_, flag)| flags | flag);
+
+    if !thing.has_attribute(DatAttributesHeader::NotMoveable) {
+        flags |= FLAG_MOVEABLE;
+    }
+    if let Some(DatAttributes::LensHelp(LENS_HELP_READABLE)) = thing.get_attribute(DatAttributesHeader::LensHelp) {
+        flags |= FLAG_READABLE;
+    }
+    if thing.phases(FrameGroupType::Idle) > 1 {
+        flags |= FLAG_ANIMATION;
+    }
+    flags
+}
+
+// the layout of ItemEditor (ottools/ItemEditor), see Sprite.GetRGBAData there
+/// The sprite hash ItemEditor stores in items.otb, md5 of every sprite of the
+/// first pattern and phase with rows bottom-up and a pixel as blue, green, red
+/// and a zero byte. Missing sprites count as transparent. The hash tells whether
+/// the look of an item changed between two clients.
+pub fn sprite_hash(thing: &Thing, spr: &SpriteData) -> [u8; 16] {
+    let mut pixels = Vec::new();
+    for id in thing.first_sprites(FrameGroupType::Idle) {
+        let image = spr.get_image(id);
+        for y in (0..32).rev() {
+            for x in 0..32 {
+                match image.map(|image| image.get_pixel(x, y).data) {
+                    Some([r, g, b, a]) if a != 0 => pixels.extend_from_slice(&[b, g, r, 0]),
+                    _ => pixels.extend_from_slice(&[TRANSPARENT_COLOR, TRANSPARENT_COLOR, TRANSPARENT_COLOR, 0]),
+                }
+            }
+        }
+    }
+    md5(&pixels)
+}
+
+/// A new otb item for a dat item, with everything the dat knows about it.
+pub fn item_from_thing(server_id: u16, thing: &Thing, spr: &SpriteData) -> ItemCategory {
+    let mut item_type = ItemType::new(server_id, thing.id());
+    item_type.flags = item_flags(thing);
+    item_type.speed = thing.ground_speed().unwrap_or(0);
+    item_type.minimap_color = thing.minimap_color().unwrap_or(0) as u16;
+    item_type.sprite_hash = Some(sprite_hash(thing, spr));
+    if let Some((level, color)) = thing.light() {
+        item_type.light_level = level;
+        item_type.light_color = color;
+    }
+    item_type.top_order = match thing.stack_priority() {
+        x @ 1..=3 => x,
+        _ => 0
+    };
+
+    if thing.has_attribute(DatAttributesHeader::Ground) {
+        ItemCategory::Ground(item_type)
+    } else if thing.has_attribute(DatAttributesHeader::Container) {
+        ItemCategory::Container(item_type)
+    } else if thing.has_attribute(DatAttributesHeader::FluidContainer) {
+        ItemCategory::Fluid(item_type)
+    } else if thing.has_attribute(DatAttributesHeader::Splash) {
+        ItemCategory::Splash(item_type)
+    } else {
+        ItemCategory::Invalid(item_type)
+    }
+}
+
+/// Appends an item for every dat item no otb item is drawn with, in client id
+/// order after the highest server id, and reports the existing items whose
+/// flags no longer match the dat.
+pub fn update(otb: &mut OtbItems, dat: &Things, spr: &SpriteData) -> UpdateReport {
+    let mut report = UpdateReport::default();
+    let things = match dat.get(&ThingCategory::Item) {
+        Some(x) => x,
+        None => return report
+    };
+
+    for item_type in otb.iter().filter(|x| !x.is_placeholder()).map(|x| x.item_type()) {
+        if let Some(thing) = things.get(&item_type.client_id()) {
+            let dat_flags = item_flags(thing);
+            if item_type.flags() & DAT_FLAGS != dat_flags {
+                report.drifted.push(FlagDrift {
+                    server_id: item_type.server_id(),
+                    client_id: item_type.client_id(),
+                    otb_flags: item_type.flags() & DAT_FLAGS,
+                    dat_flags,
+                });
+            }
+        }
+    }
+
+    let mut client_ids = things.keys().filter(|&&x| otb.get_by_client_id(x).is_none()).cloned().collect::<Vec<_>>();
+    client_ids.sort();
+
+    let mut server_id = otb.iter().last().map(|x| x.item_type().server_id()).unwrap_or(99);
+    for client_id in client_ids {
+        server_id = match server_id.checked_add(1) {
+            Some(x) => x,
+            None => break
+        };
+        otb.insert(item_from_thing(server_id, &things[&client_id], spr));
+        report.added.push(server_id);
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use dat::{parse_items, DatOptions};
+    use draw::Image;
+    use image::{ImageBuffer, Rgba};
+    use mem_read::MemReader;
+
+    fn hex(hash: [u8; 16]) -> String {
+        hash.iter().map(|x| format!("{:02x}", x)).collect()
+    }
+
+    #[test]
+    fn update_from_dat() {
+        let data: &[u8] = &[
+            102, 0, 0, 0, 0, 0, 0, 0, // item, creature, effect and missile counts
+            0, 150, 0, 255, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, // item 100: ground with speed 150, sprite 1
+            12, 13, 255, 1, 1, 1, 1, 1, 1, 1, 2, 0, 0, 0, // item 101: not walkable and not moveable, sprite 2
+            5, 17, 255, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, // item 102: stackable and pickupable, no sprite
+        ];
+        let dat = parse_items(&mut MemReader::new(data), DatOptions::default()).unwrap();
+
+        let grass: Image = ImageBuffer::from_pixel(32, 32, Rgba([0, 204, 0, 255]));
+        let mut sprites = HashMap::new();
+        sprites.insert(1, grass.clone());
+        let spr = SpriteData { signature: 0, sprites };
+
+        let mut wall = ItemType::new(100, 101);
+        wall.set_flags(FLAG_BLOCK_SOLID | FLAG_CANNOT_DECAY);
+        let mut coin = ItemType::new(101, 102);
+        coin.set_flags(FLAG_PICKUPABLE | FLAG_MOVEABLE);
+        let mut otb = OtbItems::default();
+        otb.insert(ItemCategory::Invalid(wall));
+        otb.insert(ItemCategory::Invalid(coin));
+
+        let report = update(&mut otb, &dat, &spr);
+        assert_eq!(report.added, [102]);
+        assert_eq!(report.drifted, [FlagDrift {
+            server_id: 101,
+            client_id: 102,
+            otb_flags: FLAG_PICKUPABLE | FLAG_MOVEABLE,
+            dat_flags: FLAG_PICKUPABLE | FLAG_MOVEABLE | FLAG_STACKABLE,
+        }]);
+
+        let ground = otb.get_by_client_id(100).unwrap();
+        assert_eq!(ground.server_id(), 102);
+        assert_eq!(ground.speed(), 150);
+        assert_eq!(ground.flags(), FLAG_MOVEABLE);
+        assert_eq!(ground.sprite_hash().map(|x| hex(*x)), Some("0d4089fcd7023eee121c5c63245e26d6".to_string()));
+        match otb.iter().last() {
+            Some(ItemCategory::Ground(_)) => {},
+            x => panic!("expected a ground item, got {:?}", x)
+        }
+
+        // a second run has nothing left to add
+        assert!(update(&mut otb, &dat, &spr).added.is_empty());
+    }
+
+    #[test]
+    fn sprite_hash_layout() {
+        let data: &[u8] = &[
+            101, 0, 0, 0, 0, 0, 0, 0, // item, creature, effect and missile counts
+            255, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, // item 100: sprite 1
+            255, 1, 1, 1, 1, 1, 1, 1, 2, 0, 0, 0, // item 101: sprite 2, not in the spr
+        ];
+        let dat = parse_items(&mut MemReader::new(data), DatOptions::default()).unwrap();
+
+        let mut corner: Image = ImageBuffer::new(32, 32);
+        corner.put_pixel(0, 0, Rgba([0x10, 0x20, 0x30, 255]));
+        let mut sprites = HashMap::new();
+        sprites.insert(1, corner);
+        let spr = SpriteData { signature: 0, sprites };
+
+        let items = &dat[&ThingCategory::Item];
+        assert_eq!(hex(sprite_hash(&items[&100], &spr)), "0969230ae9cb03ea7f5657c7ab07afa9");
+        assert_eq!(hex(sprite_hash(&items[&101], &spr)), "4b1b1c88ff2faf290ebc392b116d101c");
+    }
+}
@@ -2,20 +2,21 @@
 
 #![allow(dead_code)]
 
+use std::collections::HashMap;
 use std::fs::File;
 //use std::io::prelude::*;
-use std::io::{Error, Read};
+use std::io::Write;
 
-use num_traits::{FromPrimitive, ToPrimitive};
+use num_traits::FromPrimitive;
 
+use error::Error;
 use mem_read::*;
-
-const NODE_ESC: u8 = 0xFD;
-const NODE_INIT: u8 = 0xFE;
-const NODE_TERM: u8 = 0xFF;
+use mem_write::*;
+use binary_tree::*;
+use rect::Rect;
 
 #[derive(Primitive)]
-enum Header {
+enum NodeType {
     MapHeader = 0x00,
     MapData = 0x02,
     TileArea = 0x04,
@@ -28,9 +29,126 @@ enum Header {
     Waypoint = 0x10,
 }
 
+#[derive(Primitive)]
+enum ItemAttributeType {
+    ActionId = 0x04,
+    UniqueId = 0x05,
+    Text = 0x06,
+    Description = 0x07,
+    TeleportDestination = 0x08,
+    DepotId = 0x0A,
+    RuneCharges = 0x0C,
+    HouseDoorId = 0x0E,
+    Count = 0x0F,
+    Duration = 0x10,
+    DecayingState = 0x11,
+    WrittenDate = 0x12,
+    WrittenBy = 0x13,
+    SleeperGuid = 0x14,
+    SleepStart = 0x15,
+    Charges = 0x16,
+}
+
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Position {
+    pub x: u16,
+    pub y: u16,
+    pub z: u8,
+}
+
+impl Position {
+    pub fn new(x: u16, y: u16, z: u8) -> Position {
+        Position { x, y, z }
+    }
+
+    fn parse<T: MemRead>(data: &mut T) -> Result<Position, Error> {
+        Ok(Position {
+            x: data.get()?,
+            y: data.get()?,
+            z: data.get()?,
+        })
+    }
+
+    fn write<T: Write>(&self, data: &mut T) -> Result<(), Error> {
+        data.put(self.x)?;
+        data.put(self.y)?;
+        data.put(self.z)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ItemAttribute {
+    Count(u8),
+    ActionId(u16),
+    UniqueId(u16),
+    Text(String),
+    WrittenDate(u32),
+    WrittenBy(String),
+    Description(String),
+    RuneCharges(u8),
+    Charges(u16),
+    Duration(u32),
+    DecayingState(u8),
+    DepotId(u16),
+    HouseDoorId(u8),
+    TeleportDestination(Position),
+    SleeperGuid(u32),
+    SleepStart(u32),
+}
+
+impl ItemAttribute {
+    fn parse<T: MemRead>(attribute: u8, data: &mut T) -> Result<ItemAttribute, Error> {
+        let attribute_type = match ItemAttributeType::from_u8(attribute) {
+            Some(x) => x,
+            None => return Err(Error::UnknownAttribute { attribute, offset: data.position() - 1 })
+        };
+
+        Ok(match attribute_type {
+            ItemAttributeType::Count => ItemAttribute::Count(data.get()?),
+            ItemAttributeType::ActionId => ItemAttribute::ActionId(data.get()?),
+            ItemAttributeType::UniqueId => ItemAttribute::UniqueId(data.get()?),
+            ItemAttributeType::Text => ItemAttribute::Text(data.get_str()?),
+            ItemAttributeType::WrittenDate => ItemAttribute::WrittenDate(data.get()?),
+            ItemAttributeType::WrittenBy => ItemAttribute::WrittenBy(data.get_str()?),
+            ItemAttributeType::Description => ItemAttribute::Description(data.get_str()?),
+            ItemAttributeType::RuneCharges => ItemAttribute::RuneCharges(data.get()?),
+            ItemAttributeType::Charges => ItemAttribute::Charges(data.get()?),
+            ItemAttributeType::Duration => ItemAttribute::Duration(data.get()?),
+            ItemAttributeType::DecayingState => ItemAttribute::DecayingState(data.get()?),
+            ItemAttributeType::DepotId => ItemAttribute::DepotId(data.get()?),
+            ItemAttributeType::HouseDoorId => ItemAttribute::HouseDoorId(data.get()?),
+            ItemAttributeType::TeleportDestination => ItemAttribute::TeleportDestination(Position::parse(data)?),
+            ItemAttributeType::SleeperGuid => ItemAttribute::SleeperGuid(data.get()?),
+            ItemAttributeType::SleepStart => ItemAttribute::SleepStart(data.get()?),
+        })
+    }
+
+    fn write<T: Write>(&self, data: &mut T) -> Result<(), Error> {
+        match self {
+            ItemAttribute::Count(x) => { data.put(ItemAttributeType::Count as u8)?; data.put(*x)?; },
+            ItemAttribute::ActionId(x) => { data.put(ItemAttributeType::ActionId as u8)?; data.put(*x)?; },
+            ItemAttribute::UniqueId(x) => { data.put(ItemAttributeType::UniqueId as u8)?; data.put(*x)?; },
+            ItemAttribute::Text(x) => { data.put(ItemAttributeType::Text as u8)?; data.put_str(x)?; },
+            ItemAttribute::WrittenDate(x) => { data.put(ItemAttributeType::WrittenDate as u8)?; data.put(*x)?; },
+            ItemAttribute::WrittenBy(x) => { data.put(ItemAttributeType::WrittenBy as u8)?; data.put_str(x)?; },
+            ItemAttribute::Description(x) => { data.put(ItemAttributeType::Description as u8)?; data.put_str(x)?; },
+            ItemAttribute::RuneCharges(x) => { data.put(ItemAttributeType::RuneCharges as u8)?; data.put(*x)?; },
+            ItemAttribute::Charges(x) => { data.put(ItemAttributeType::Charges as u8)?; data.put(*x)?; },
+            ItemAttribute::Duration(x) => { data.put(ItemAttributeType::Duration as u8)?; data.put(*x)?; },
+            ItemAttribute::DecayingState(x) => { data.put(ItemAttributeType::DecayingState as u8)?; data.put(*x)?; },
+            ItemAttribute::DepotId(x) => { data.put(ItemAttributeType::DepotId as u8)?; data.put(*x)?; },
+            ItemAttribute::HouseDoorId(x) => { data.put(ItemAttributeType::HouseDoorId as u8)?; data.put(*x)?; },
+            ItemAttribute::TeleportDestination(x) => { data.put(ItemAttributeType::TeleportDestination as u8)?; x.write(data)?; },
+            ItemAttribute::SleeperGuid(x) => { data.put(ItemAttributeType::SleeperGuid as u8)?; data.put(*x)?; },
+            ItemAttribute::SleepStart(x) => { data.put(ItemAttributeType::SleepStart as u8)?; data.put(*x)?; },
+        }
+        Ok(())
+    }
+}
+
 #[derive(PartialEq)]
 pub enum Node {
-    Unknown,
     MapHeader(MapHeaderNode),
     MapData(MapDataNode),
     TileArea(TileAreaNode),
@@ -43,6 +161,43 @@ pub enum Node {
     Waypoint(WaypointNode),
 }
 
+impl Node {
+    fn parse<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<Node, Error> {
+        let node_type = match NodeType::from_u8(node.node_type()) {
+            Some(x) => x,
+            None => return Err(Error::UnknownNodeType { node_type: node.node_type(), offset: node.offset() })
+        };
+
+        Ok(match node_type {
+            NodeType::MapHeader => Node::MapHeader(MapHeaderNode::from_node(node, tree)?),
+            NodeType::MapData => Node::MapData(MapDataNode::from_node(node, tree)?),
+            NodeType::TileArea => Node::TileArea(TileAreaNode::from_node(node, tree)?),
+            NodeType::Tile => Node::Tile(TileNode::from_node(node, tree)?),
+            NodeType::Item => Node::Item(ItemNode::from_node(node, tree)?),
+            NodeType::Towns => Node::Towns(TownsNode::from_node(node, tree)?),
+            NodeType::Town => Node::Town(TownNode::from_node(node, tree)?),
+            NodeType::HouseTile => Node::HouseTile(HouseTileNode::from_node(node, tree)?),
+            NodeType::Waypoints => Node::Waypoints(WaypointsNode::from_node(node, tree)?),
+            NodeType::Waypoint => Node::Waypoint(WaypointNode::from_node(node, tree)?),
+        })
+    }
+
+    fn node_type(&self) -> NodeType {
+        match self {
+            Node::MapHeader(_) => NodeType::MapHeader,
+            Node::MapData(_) => NodeType::MapData,
+            Node::TileArea(_) => NodeType::TileArea,
+            Node::Tile(_) => NodeType::Tile,
+            Node::Item(_) => NodeType::Item,
+            Node::Towns(_) => NodeType::Towns,
+            Node::Town(_) => NodeType::Town,
+            Node::HouseTile(_) => NodeType::HouseTile,
+            Node::Waypoints(_) => NodeType::Waypoints,
+            Node::Waypoint(_) => NodeType::Waypoint,
+        }
+    }
+}
+
 impl std::fmt::Display for Node {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -51,20 +206,40 @@ impl std::fmt::Display for Node {
                 "MapHeader - Width: {} Height: {}",
                 x.map_width, x.map_height
             ),
-            Node::MapData(x) => write!(f, "MapData"),
+            Node::MapData(_) => write!(f, "MapData"),
             Node::TileArea(x) => write!(f, "TileArea x: {} y: {} z: {}", x.x, x.y, x.z),
             Node::Tile(x) => write!(f, "Tile x: {} y: {}", x.x, x.y),
             Node::Item(x) => write!(f, "Item id: {}", x.id),
-            Node::Towns(x) => write!(f, "Towns"),
-            Node::Town(x) => write!(f, "Town"),
-            Node::HouseTile(x) => write!(f, "HouseTile"),
-            Node::Waypoints(x) => write!(f, "Waypoints"),
-            Node::Waypoint(x) => write!(f, "Waypoint"),
-            _ => write!(f, "Unknown"),
+            Node::Towns(_) => write!(f, "Towns"),
+            Node::Town(_) => write!(f, "Town"),
+            Node::HouseTile(x) => write!(f, "HouseTile x: {} y: {} house id: {}", x.tile.x, x.tile.y, x.house_id),
+            Node::Waypoints(_) => write!(f, "Waypoints"),
+            Node::Waypoint(_) => write!(f, "Waypoint"),
         }
     }
 }
 
+#[derive(Primitive)]
+enum MapAttributeType {
+    Description = 0x01,
+    TileFlags = 0x03,
+    Item = 0x09,
+    SpawnFile = 0x0B,
+    HouseFile = 0x0D,
+}
+
+fn unexpected_node(node: &Node, offset: usize) -> Error {
+    Error::UnexpectedNode { node_type: node.node_type() as u8, offset }
+}
+
+/// A node `visit_map` does not expect at its place, it might not be known at all.
+fn unexpected_raw_node(node: &RawNode) -> Error {
+    match NodeType::from_u8(node.node_type()) {
+        Some(_) => Error::UnexpectedNode { node_type: node.node_type(), offset: node.offset() },
+        None => Error::UnknownNodeType { node_type: node.node_type(), offset: node.offset() },
+    }
+}
+
 #[derive(PartialEq)]
 pub struct MapHeaderNode {
     version: u32,
@@ -72,26 +247,95 @@ pub struct MapHeaderNode {
     map_height: u16,
     items_major_version: u32,
     items_minor_version: u32,
+    map_data: MapDataNode,
 }
 
-impl MapHeaderNode {
-    fn parse<T: MemRead>(data: &mut T) -> Result<MapHeaderNode, Error> {
+impl HasChildren for MapHeaderNode {
+    type Output = MapHeaderNode;
+    type Child = (usize, Node);
+
+    fn parse<T: MemRead>(data: &mut T, children: Vec<(usize, Node)>) -> Result<MapHeaderNode, Error> {
+        let offset = data.position() - 2; // node start and type
+        let version = data.get()?;
+        if version > 3 {
+            return Err(Error::UnsupportedVersion { version, offset: data.position() - 4 });
+        }
+
+        let map_width = data.get()?;
+        let map_height = data.get()?;
+        let items_major_version = data.get()?;
+        let items_minor_version = data.get()?;
+
+        let mut map_data = None;
+        for (offset, child) in children {
+            match child {
+                Node::MapData(x) => map_data = Some(x),
+                x => return Err(unexpected_node(&x, offset))
+            }
+        }
+
         Ok(MapHeaderNode {
-            version: data.get()?,
-            map_width: data.get()?,
-            map_height: data.get()?,
-            items_major_version: data.get()?,
-            items_minor_version: data.get()?,
+            version,
+            map_width,
+            map_height,
+            items_major_version,
+            items_minor_version,
+            map_data: map_data.ok_or(Error::MissingNode { node_type: NodeType::MapData as u8, offset })?,
         })
     }
+
+    fn parse_child<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<(usize, Node), Error> {
+        Ok((node.offset(), Node::parse(node, tree)?))
+    }
 }
 
 #[derive(PartialEq)]
-pub struct MapDataNode {}
+pub struct MapDataNode {
+    description: Vec<String>,
+    spawn_file: String,
+    house_file: String,
+    tile_areas: Vec<TileAreaNode>,
+    towns: Vec<TownNode>,
+    waypoints: Vec<WaypointNode>,
+}
+
+impl HasChildren for MapDataNode {
+    type Output = MapDataNode;
+    type Child = (usize, Node);
+
+    fn parse<T: MemRead>(data: &mut T, children: Vec<(usize, Node)>) -> Result<MapDataNode, Error> {
+        let mut map_data = MapDataNode {
+            description: Vec::new(),
+            spawn_file: String::new(),
+            house_file: String::new(),
+            tile_areas: Vec::new(),
+            towns: Vec::new(),
+            waypoints: Vec::new(),
+        };
+
+        while let Ok(attribute) = data.get::<u8>() {
+            match MapAttributeType::from_u8(attribute) {
+                Some(MapAttributeType::Description) => map_data.description.push(data.get_str()?),
+                Some(MapAttributeType::SpawnFile) => map_data.spawn_file = data.get_str()?,
+                Some(MapAttributeType::HouseFile) => map_data.house_file = data.get_str()?,
+                _ => return Err(Error::UnknownAttribute { attribute, offset: data.position() - 1 })
+            }
+        }
+
+        for (offset, child) in children {
+            match child {
+                Node::TileArea(x) => map_data.tile_areas.push(x),
+                Node::Towns(x) => map_data.towns.extend(x.towns),
+                Node::Waypoints(x) => map_data.waypoints.extend(x.waypoints),
+                x => return Err(unexpected_node(&x, offset))
+            }
+        }
+
+        Ok(map_data)
+    }
 
-impl MapDataNode {
-    fn parse<T: MemRead>(data: &mut T) -> Result<MapDataNode, Error> {
-        Ok(MapDataNode {})
+    fn parse_child<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<(usize, Node), Error> {
+        Ok((node.offset(), Node::parse(node, tree)?))
     }
 }
 
@@ -100,16 +344,34 @@ pub struct TileAreaNode {
     x: u16,
     y: u16,
     z: u8,
-    //tiles: Vec<TileNode>
+    tiles: Vec<(Position, TileNode)>,
 }
 
-impl TileAreaNode {
-    fn parse<T: MemRead>(data: &mut T) -> Result<TileAreaNode, Error> {
-        Ok(TileAreaNode {
-            x: data.get()?,
-            y: data.get()?,
-            z: data.get()?,
-        })
+impl HasChildren for TileAreaNode {
+    type Output = TileAreaNode;
+    type Child = (usize, Node);
+
+    fn parse<T: MemRead>(data: &mut T, children: Vec<(usize, Node)>) -> Result<TileAreaNode, Error> {
+        let x = data.get()?;
+        let y = data.get()?;
+        let z = data.get()?;
+
+        let area = Position::new(x, y, z);
+        let mut tiles = Vec::new();
+        for (offset, child) in children {
+            let tile = match child {
+                Node::Tile(x) => x,
+                Node::HouseTile(x) => x.tile,
+                x => return Err(unexpected_node(&x, offset))
+            };
+            tiles.push((tile.position(area, offset)?, tile));
+        }
+
+        Ok(TileAreaNode { x, y, z, tiles })
+    }
+
+    fn parse_child<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<(usize, Node), Error> {
+        Ok((node.offset(), Node::parse(node, tree)?))
     }
 }
 
@@ -117,86 +379,248 @@ impl TileAreaNode {
 pub struct TileNode {
     x: u8,
     y: u8,
-    //items: Vec<ItemNode>
+    house_id: Option<u32>,
+    flags: u32,
+    items: Vec<ItemNode>,
+}
+
+impl HasChildren for TileNode {
+    type Output = TileNode;
+    type Child = (usize, Node);
+
+    fn parse<T: MemRead>(data: &mut T, children: Vec<(usize, Node)>) -> Result<TileNode, Error> {
+        let x = data.get()?;
+        let y = data.get()?;
+        TileNode::parse_content(x, y, None, data, children)
+    }
+
+    fn parse_child<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<(usize, Node), Error> {
+        Ok((node.offset(), Node::parse(node, tree)?))
+    }
 }
 
 impl TileNode {
-    fn parse<T: MemRead>(data: &mut T) -> Result<TileNode, Error> {
-        Ok(TileNode {
-            x: data.get()?,
-            y: data.get()?,
-        })
+    /// The position of the tile in the area starting at `area`, fails with the
+    /// `offset` of the tile node if it lies outside of the map.
+    fn position(&self, area: Position, offset: usize) -> Result<Position, Error> {
+        match (area.x.checked_add(self.x as u16), area.y.checked_add(self.y as u16)) {
+            (Some(x), Some(y)) => Ok(Position::new(x, y, area.z)),
+            _ => Err(Error::PositionOverflow { offset })
+        }
+    }
+
+    fn parse_content<T: MemRead>(x: u8, y: u8, house_id: Option<u32>, data: &mut T, children: Vec<(usize, Node)>) -> Result<TileNode, Error> {
+        let mut tile = TileNode { x, y, house_id, flags: 0, items: Vec::new() };
+
+        while let Ok(attribute) = data.get::<u8>() {
+            match MapAttributeType::from_u8(attribute) {
+                Some(MapAttributeType::TileFlags) => tile.flags = data.get()?,
+                Some(MapAttributeType::Item) => tile.items.push(ItemNode::new(data.get()?)),
+                _ => return Err(Error::UnknownAttribute { attribute, offset: data.position() - 1 })
+            }
+        }
+
+        for (offset, child) in children {
+            match child {
+                Node::Item(x) => tile.items.push(x),
+                x => return Err(unexpected_node(&x, offset))
+            }
+        }
+
+        Ok(tile)
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ItemNode {
     id: u16,
-    // content: Vec<???>
+    attributes: Vec<ItemAttribute>,
+    items: Vec<ItemNode>,
+}
+
+impl HasChildren for ItemNode {
+    type Output = ItemNode;
+    type Child = (usize, Node);
+
+    fn parse<T: MemRead>(data: &mut T, children: Vec<(usize, Node)>) -> Result<ItemNode, Error> {
+        let mut item = ItemNode::new(data.get()?);
+        while let Ok(attribute) = data.get::<u8>() {
+            item.attributes.push(ItemAttribute::parse(attribute, data)?);
+        }
+
+        for (offset, child) in children {
+            match child {
+                Node::Item(x) => item.items.push(x),
+                x => return Err(unexpected_node(&x, offset))
+            }
+        }
+
+        Ok(item)
+    }
+
+    fn parse_child<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<(usize, Node), Error> {
+        Ok((node.offset(), Node::parse(node, tree)?))
+    }
 }
 
 impl ItemNode {
-    fn parse<T: MemRead>(data: &mut T) -> Result<ItemNode, Error> {
-        Ok(ItemNode { id: data.get()? })
+    pub fn new(id: u16) -> ItemNode {
+        ItemNode { id, attributes: Vec::new(), items: Vec::new() }
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn attributes(&self) -> &[ItemAttribute] {
+        &self.attributes
+    }
+
+    pub fn attributes_mut(&mut self) -> &mut Vec<ItemAttribute> {
+        &mut self.attributes
+    }
+
+    /// Items stored inside of this item, e.g. the content of a container.
+    pub fn items(&self) -> &[ItemNode] {
+        &self.items
+    }
+
+    pub fn items_mut(&mut self) -> &mut Vec<ItemNode> {
+        &mut self.items
+    }
+
+    fn write<T: Write>(&self, data: &mut T) -> Result<(), Error> {
+        let mut props: Vec<u8> = Vec::new();
+        props.put(self.id)?;
+        for attribute in &self.attributes {
+            attribute.write(&mut props)?;
+        }
+
+        write_node_start(data, NodeType::Item as u8, &props)?;
+        for item in &self.items {
+            item.write(data)?;
+        }
+        write_node_end(data)
+    }
+
+    pub fn action_id(&self) -> Option<u16> {
+        self.attributes.iter().filter_map(|x| match x {
+            ItemAttribute::ActionId(id) => Some(*id),
+            _ => None
+        }).next()
+    }
+
+    pub fn unique_id(&self) -> Option<u16> {
+        self.attributes.iter().filter_map(|x| match x {
+            ItemAttribute::UniqueId(id) => Some(*id),
+            _ => None
+        }).next()
+    }
+
+    pub fn teleport_destination(&self) -> Option<Position> {
+        self.attributes.iter().filter_map(|x| match x {
+            ItemAttribute::TeleportDestination(pos) => Some(*pos),
+            _ => None
+        }).next()
     }
 }
 
 #[derive(PartialEq)]
 pub struct HouseTileNode {
-    x: u16,
-    y: u16,
     house_id: u32,
-    //items: Vec<ItemNode>
+    tile: TileNode,
 }
 
-impl HouseTileNode {
-    fn parse<T: MemRead>(data: &mut T) -> Result<HouseTileNode, Error> {
+impl HasChildren for HouseTileNode {
+    type Output = HouseTileNode;
+    type Child = (usize, Node);
+
+    fn parse<T: MemRead>(data: &mut T, children: Vec<(usize, Node)>) -> Result<HouseTileNode, Error> {
+        let x = data.get()?;
+        let y = data.get()?;
+        let house_id = data.get()?;
         Ok(HouseTileNode {
-            x: data.get()?,
-            y: data.get()?,
-            house_id: data.get()?,
+            house_id,
+            tile: TileNode::parse_content(x, y, Some(house_id), data, children)?,
         })
     }
+
+    fn parse_child<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<(usize, Node), Error> {
+        Ok((node.offset(), Node::parse(node, tree)?))
+    }
 }
 
 #[derive(PartialEq)]
 pub struct WaypointsNode {
-    // nodes: Vec<WaypontNode>
+    waypoints: Vec<WaypointNode>,
 }
 
-impl WaypointsNode {
-    fn parse<T: MemRead>(data: &mut T) -> Result<WaypointsNode, Error> {
-        Ok(WaypointsNode {})
+impl HasChildren for WaypointsNode {
+    type Output = WaypointsNode;
+    type Child = (usize, Node);
+
+    fn parse<T: MemRead>(_data: &mut T, children: Vec<(usize, Node)>) -> Result<WaypointsNode, Error> {
+        let mut waypoints = Vec::new();
+        for (offset, child) in children {
+            match child {
+                Node::Waypoint(x) => waypoints.push(x),
+                x => return Err(unexpected_node(&x, offset))
+            }
+        }
+
+        Ok(WaypointsNode { waypoints })
+    }
+
+    fn parse_child<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<(usize, Node), Error> {
+        Ok((node.offset(), Node::parse(node, tree)?))
     }
 }
 
 #[derive(PartialEq)]
 pub struct WaypointNode {
     name: String,
-    x: u16,
-    y: u16,
-    z: u8,
+    position: Position,
 }
 
-impl WaypointNode {
-    fn parse<T: MemRead>(data: &mut T) -> Result<WaypointNode, Error> {
+impl HasChildren for WaypointNode {
+    type Output = WaypointNode;
+    type Child = (usize, Node);
+
+    fn parse<T: MemRead>(data: &mut T, _children: Vec<(usize, Node)>) -> Result<WaypointNode, Error> {
         Ok(WaypointNode {
-            name: String::new(),
-            x: 0,
-            y: 0,
-            z: 0,
+            name: data.get_str()?,
+            position: Position::parse(data)?,
         })
     }
+
+    fn parse_child<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<(usize, Node), Error> {
+        Ok((node.offset(), Node::parse(node, tree)?))
+    }
 }
 
 #[derive(PartialEq)]
 pub struct TownsNode {
-    //towns: Vec<TownNode>
+    towns: Vec<TownNode>,
 }
 
-impl TownsNode {
-    fn parse<T: MemRead>(data: &mut T) -> Result<TownsNode, Error> {
-        Ok(TownsNode {})
+impl HasChildren for TownsNode {
+    type Output = TownsNode;
+    type Child = (usize, Node);
+
+    fn parse<T: MemRead>(_data: &mut T, children: Vec<(usize, Node)>) -> Result<TownsNode, Error> {
+        let mut towns = Vec::new();
+        for (offset, child) in children {
+            match child {
+                Node::Town(x) => towns.push(x),
+                x => return Err(unexpected_node(&x, offset))
+            }
+        }
+
+        Ok(TownsNode { towns })
+    }
+
+    fn parse_child<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<(usize, Node), Error> {
+        Ok((node.offset(), Node::parse(node, tree)?))
     }
 }
 
@@ -204,79 +628,501 @@ impl TownsNode {
 pub struct TownNode {
     town_id: u32,
     name: String,
-    x: u16,
-    y: u16,
-    z: u8,
+    temple: Position,
 }
 
-impl TownNode {
-    fn parse<T: MemRead>(data: &mut T) -> Result<TownNode, Error> {
+impl HasChildren for TownNode {
+    type Output = TownNode;
+    type Child = (usize, Node);
+
+    fn parse<T: MemRead>(data: &mut T, _children: Vec<(usize, Node)>) -> Result<TownNode, Error> {
         Ok(TownNode {
             town_id: data.get()?,
-            name: String::new(),
-            x: 0,
-            y: 0,
-            z: 0,
+            name: data.get_str()?,
+            temple: Position::parse(data)?,
         })
     }
+
+    fn parse_child<'a>(node: RawNode<'a>, tree: &mut TreeReader<'a>) -> Result<(usize, Node), Error> {
+        Ok((node.offset(), Node::parse(node, tree)?))
+    }
 }
 
-pub fn parse(filename: String) -> Result<Node, Error> {
-    let mut file = File::open(filename)?;
-    let mut data: Vec<u8> = Vec::new();
-    file.read_to_end(&mut data)?;
-    let data: &mut &[u8] = &mut data.as_ref();
+#[derive(Debug)]
+pub struct Tile {
+    pub position: Position,
+    pub house_id: Option<u32>,
+    pub flags: u32,
+    pub items: Vec<ItemNode>,
+}
+
+impl Tile {
+    fn write<T: Write>(&self, data: &mut T) -> Result<(), Error> {
+        let mut props: Vec<u8> = Vec::new();
+        props.put((self.position.x & 0xFF) as u8)?;
+        props.put((self.position.y & 0xFF) as u8)?;
+        let node_type = match self.house_id {
+            Some(house_id) => {
+                props.put(house_id)?;
+                NodeType::HouseTile
+            },
+            None => NodeType::Tile
+        };
+
+        if self.flags != 0 {
+            props.put(MapAttributeType::TileFlags as u8)?;
+            props.put(self.flags)?;
+        }
+
+        write_node_start(data, node_type as u8, &props)?;
+        for item in &self.items {
+            item.write(data)?;
+        }
+        write_node_end(data)
+    }
+}
+
+#[derive(Debug)]
+pub struct Town {
+    pub id: u32,
+    pub name: String,
+    pub temple: Position,
+}
+
+#[derive(Debug)]
+pub struct Waypoint {
+    pub name: String,
+    pub position: Position,
+}
+
+#[derive(Debug)]
+pub struct Map {
+    pub version: u32,
+    pub width: u16,
+    pub height: u16,
+    pub items_major_version: u32,
+    pub items_minor_version: u32,
+    pub description: Vec<String>,
+    pub spawn_file: String,
+    pub house_file: String,
+    pub towns: Vec<Town>,
+    pub waypoints: Vec<Waypoint>,
+    pub tiles: HashMap<Position, Tile>,
+}
+
+impl Map {
+    fn new(header: MapHeaderNode) -> Map {
+        let map_data = header.map_data;
+        let mut tiles = HashMap::new();
+        for area in map_data.tile_areas {
+            for (position, tile) in area.tiles {
+                tiles.insert(position, Tile {
+                    position,
+                    house_id: tile.house_id,
+                    flags: tile.flags,
+                    items: tile.items,
+                });
+            }
+        }
+
+        Map {
+            version: header.version,
+            width: header.map_width,
+            height: header.map_height,
+            items_major_version: header.items_major_version,
+            items_minor_version: header.items_minor_version,
+            description: map_data.description,
+            spawn_file: map_data.spawn_file,
+            house_file: map_data.house_file,
+            towns: map_data.towns.into_iter().map(|x| Town { id: x.town_id, name: x.name, temple: x.temple }).collect(),
+            waypoints: map_data.waypoints.into_iter().map(|x| Waypoint { name: x.name, position: x.position }).collect(),
+            tiles,
+        }
+    }
+
+    pub fn get_tile(&self, position: Position) -> Option<&Tile> {
+        self.tiles.get(&position)
+    }
+
+    pub fn get_tile_mut(&mut self, position: Position) -> Option<&mut Tile> {
+        self.tiles.get_mut(&position)
+    }
+
+    /// The smallest area containing the tiles of all floors.
+    pub fn bounds(&self) -> Rect {
+        let mut positions = self.tiles.keys();
+        let first = match positions.next() {
+            Some(x) => *x,
+            None => return Rect::new(0, 0, 0, 0)
+        };
+
+        let (mut left, mut top, mut right, mut bottom) = (first.x, first.y, first.x, first.y);
+        for position in positions {
+            left = std::cmp::min(left, position.x);
+            top = std::cmp::min(top, position.y);
+            right = std::cmp::max(right, position.x);
+            bottom = std::cmp::max(bottom, position.y);
+        }
+        Rect::new(left as i32, top as i32, (right - left) as i32 + 1, (bottom - top) as i32 + 1)
+    }
+
+    fn write<T: Write>(&self, data: &mut T) -> Result<(), Error> {
+        let mut props: Vec<u8> = Vec::new();
+        props.put(self.version)?;
+        props.put(self.width)?;
+        props.put(self.height)?;
+        props.put(self.items_major_version)?;
+        props.put(self.items_minor_version)?;
+        write_node_start(data, NodeType::MapHeader as u8, &props)?;
+
+        let mut props: Vec<u8> = Vec::new();
+        for description in &self.description {
+            props.put(MapAttributeType::Description as u8)?;
+            props.put_str(description)?;
+        }
+        props.put(MapAttributeType::SpawnFile as u8)?;
+        props.put_str(&self.spawn_file)?;
+        props.put(MapAttributeType::HouseFile as u8)?;
+        props.put_str(&self.house_file)?;
+        write_node_start(data, NodeType::MapData as u8, &props)?;
+
+        // tiles are grouped into areas of 256x256 tiles, written in a fixed order
+        // so that writing the same map twice gives identical bytes
+        let mut tiles = self.tiles.values().collect::<Vec<_>>();
+        tiles.sort_by_key(|tile| (tile.position.z, tile.position.y & 0xFF00, tile.position.x & 0xFF00, tile.position.y, tile.position.x));
+
+        let mut area = None;
+        for tile in tiles {
+            let base = Position::new(tile.position.x & 0xFF00, tile.position.y & 0xFF00, tile.position.z);
+            if area != Some(base) {
+                if area.is_some() {
+                    write_node_end(data)?;
+                }
+
+                let mut props: Vec<u8> = Vec::new();
+                base.write(&mut props)?;
+                write_node_start(data, NodeType::TileArea as u8, &props)?;
+                area = Some(base);
+            }
+
+            tile.write(data)?;
+        }
+        if area.is_some() {
+            write_node_end(data)?;
+        }
+
+        write_node_start(data, NodeType::Towns as u8, &[])?;
+        for town in &self.towns {
+            let mut props: Vec<u8> = Vec::new();
+            props.put(town.id)?;
+            props.put_str(&town.name)?;
+            town.temple.write(&mut props)?;
+            write_node_start(data, NodeType::Town as u8, &props)?;
+            write_node_end(data)?;
+        }
+        write_node_end(data)?;
 
+        write_node_start(data, NodeType::Waypoints as u8, &[])?;
+        for waypoint in &self.waypoints {
+            let mut props: Vec<u8> = Vec::new();
+            props.put_str(&waypoint.name)?;
+            waypoint.position.write(&mut props)?;
+            write_node_start(data, NodeType::Waypoint as u8, &props)?;
+            write_node_end(data)?;
+        }
+        write_node_end(data)?;
+
+        write_node_end(data)?; // MapData
+        write_node_end(data) // MapHeader
+    }
+}
+
+pub fn parse(filename: String) -> Result<Map, Error> {
+    parse_map(&map_file(filename)?)
+}
+
+fn read_tree<'a>(data: &'a [u8]) -> Result<TreeReader<'a>, Error> {
+    let mut data = MemReader::new(data);
     let map_identifier = data.get::<u32>()?;
     if map_identifier != 0x0 && map_identifier != 0x4D42_544F {
-        panic!("unknown OTBM format: unexpected magic bytes.");
+        return Err(Error::BadMagic { magic: map_identifier, offset: data.position() - 4 });
     }
+    Ok(TreeReader::new(data))
+}
 
-    read_node(data, false)
+pub fn parse_map(data: &[u8]) -> Result<Map, Error> {
+    let tree = &mut read_tree(data)?;
+    let root = tree.next_node()?;
+    let offset = root.offset();
+    match Node::parse(root, tree)? {
+        Node::MapHeader(header) => Ok(Map::new(header)),
+        x => Err(unexpected_node(&x, offset))
+    }
 }
 
-// https://github.com/Inconcessus/OTBM2JSON/blob/master/otbm2json.js
-fn read_node<T: MemRead>(data: &mut T, is_child: bool) -> Result<Node, Error> {
-    let mut node = Node::Unknown;
-    let mut children = vec![];
+/// A tile handed out by `visit`, only valid for the duration of the callback.
+#[derive(Debug)]
+pub struct TileRef<'t> {
+    pub position: Position,
+    pub house_id: Option<u32>,
+    pub flags: u32,
+    pub items: &'t [ItemNode],
+}
+
+/// Calls `f` for every tile of the map, one after another and without keeping
+/// previous tiles around. Towns and waypoints are skipped.
+pub fn visit<F: FnMut(TileRef)>(filename: String, f: F) -> Result<(), Error> {
+    visit_map(&map_file(filename)?, f)
+}
+
+pub fn visit_map<F: FnMut(TileRef)>(data: &[u8], mut f: F) -> Result<(), Error> {
+    let tree = &mut read_tree(data)?;
+    let header = tree.next_node()?;
+    match NodeType::from_u8(header.node_type()) {
+        Some(NodeType::MapHeader) => {},
+        _ => return Err(unexpected_raw_node(&header))
+    }
 
-    let mut skip = false;
-    let mut first = true;
+    let version = header.props().get()?;
+    if version > 3 {
+        return Err(Error::UnsupportedVersion { version, offset: header.offset() + 2 });
+    }
 
-    loop {
-        if skip {
-            skip = false;
-            continue;
+    while let Some(map_data) = tree.next_child(&header)? {
+        match NodeType::from_u8(map_data.node_type()) {
+            Some(NodeType::MapData) => {},
+            _ => return Err(unexpected_raw_node(&map_data))
         }
 
-        let c_byte = if is_child && first {
-            first = false;
-            0xFE
-        } else {
-            data.get::<u8>()?
-        };
+        while let Some(area) = tree.next_child(&map_data)? {
+            match NodeType::from_u8(area.node_type()) {
+                Some(NodeType::TileArea) => {},
+                Some(NodeType::Towns) | Some(NodeType::Waypoints) => continue,
+                _ => return Err(unexpected_raw_node(&area))
+            }
 
-        match c_byte {
-            NODE_INIT | NODE_TERM if node == Node::Unknown => {
-                let identifier = data.get::<u8>()?;
-                node = match Header::from_u8(identifier).expect("from_u8 failed") {
-                    Header::MapHeader => Node::MapHeader(MapHeaderNode::parse(data)?),
-                    Header::MapData => Node::MapData(MapDataNode::parse(data)?),
-                    Header::TileArea => Node::TileArea(TileAreaNode::parse(data)?),
-                    Header::Tile => Node::Tile(TileNode::parse(data)?),
-                    Header::Item => Node::Item(ItemNode::parse(data)?),
-                    Header::Towns => Node::Towns(TownsNode::parse(data)?),
-                    Header::Town => Node::Town(TownNode::parse(data)?),
-                    Header::HouseTile => Node::HouseTile(HouseTileNode::parse(data)?),
-                    Header::Waypoints => Node::Waypoints(WaypointsNode::parse(data)?),
-                    Header::Waypoint => Node::Waypoint(WaypointNode::parse(data)?),
+            let base = Position::parse(&mut area.props())?;
+            while let Some(node) = tree.next_child(&area)? {
+                let offset = node.offset();
+                let tile = match NodeType::from_u8(node.node_type()) {
+                    Some(NodeType::Tile) => TileNode::from_node(node, tree)?,
+                    Some(NodeType::HouseTile) => HouseTileNode::from_node(node, tree)?.tile,
+                    _ => return Err(unexpected_raw_node(&node))
                 };
-                //println!("{}", node);
+
+                f(TileRef {
+                    position: tile.position(base, offset)?,
+                    house_id: tile.house_id,
+                    flags: tile.flags,
+                    items: &tile.items,
+                });
             }
-            NODE_ESC => skip = true,
-            NODE_INIT => children.push(read_node(data, true)?),
-            NODE_TERM => return Ok(node),
-            _ => (), //println!("unused_byte: 0x{:02X}", x)
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write(filename: String, map: &Map) -> Result<(), Error> {
+    let mut data: Vec<u8> = Vec::new();
+    write_map(&mut data, map)?;
+    File::create(filename)?.write_all(&data)?;
+    Ok(())
+}
+
+pub fn write_map<T: Write>(data: &mut T, map: &Map) -> Result<(), Error> {
+    data.put(0u32)?; // identifier
+    map.write(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_map() -> Map {
+        let mut chest = ItemNode::new(1740);
+        chest.attributes_mut().push(ItemAttribute::UniqueId(0xFEFD));
+        chest.attributes_mut().push(ItemAttribute::ActionId(2000));
+        let mut letter = ItemNode::new(2597);
+        letter.attributes_mut().push(ItemAttribute::Text("hello \u{FF} world".to_string()));
+        letter.attributes_mut().push(ItemAttribute::WrittenBy("Shawak".to_string()));
+        letter.attributes_mut().push(ItemAttribute::WrittenDate(0xFFFF_FFFF));
+        chest.items_mut().push(letter);
+
+        let mut teleport = ItemNode::new(1387);
+        teleport.attributes_mut().push(ItemAttribute::TeleportDestination(Position::new(32369, 32241, 7)));
+
+        let mut tiles = HashMap::new();
+        for (position, house_id, flags, items) in [
+            (Position::new(32369, 32241, 7), None, 0, vec![ItemNode::new(4526)]),
+            (Position::new(32370, 32241, 7), None, 0x0004, vec![ItemNode::new(4526), teleport]),
+            (Position::new(255, 256, 8), Some(0xFE), 0, vec![ItemNode::new(405), chest]),
+            (Position::new(256, 256, 8), Some(0xFE), 0, vec![]),
+        ] {
+            tiles.insert(position, Tile { position, house_id, flags, items });
+        }
+
+        Map {
+            version: 2,
+            width: 2048,
+            height: 2048,
+            items_major_version: 3,
+            items_minor_version: 57,
+            description: vec!["Saved with otbmview".to_string(), "test map".to_string()],
+            spawn_file: "test-spawn.xml".to_string(),
+            house_file: "test-house.xml".to_string(),
+            towns: vec![Town { id: 1, name: "Thais".to_string(), temple: Position::new(32369, 32241, 7) }],
+            waypoints: vec![Waypoint { name: "temple".to_string(), position: Position::new(32369, 32241, 7) }],
+            tiles,
+        }
+    }
+
+    #[test]
+    fn write_parse_round_trip() {
+        let mut written: Vec<u8> = Vec::new();
+        write_map(&mut written, &test_map()).unwrap();
+
+        let map = parse_map(&written).unwrap();
+        assert_eq!(map.tiles.len(), 4);
+        assert_eq!(map.description.len(), 2);
+        assert_eq!(map.towns[0].name, "Thais");
+        assert_eq!(map.waypoints[0].position, Position::new(32369, 32241, 7));
+
+        let house_tile = map.get_tile(Position::new(255, 256, 8)).unwrap();
+        assert_eq!(house_tile.house_id, Some(0xFE));
+        assert_eq!(house_tile.items[1].unique_id(), Some(0xFEFD));
+        assert_eq!(house_tile.items[1].items()[0].attributes()[0], ItemAttribute::Text("hello \u{FF} world".to_string()));
+
+        let teleport_tile = map.get_tile(Position::new(32370, 32241, 7)).unwrap();
+        assert_eq!(teleport_tile.flags, 0x0004);
+        assert_eq!(teleport_tile.items[1].teleport_destination(), Some(Position::new(32369, 32241, 7)));
+
+        let mut rewritten: Vec<u8> = Vec::new();
+        write_map(&mut rewritten, &map).unwrap();
+        assert_eq!(written, rewritten);
+    }
+
+    #[test]
+    fn visit_tiles() {
+        let map = test_map();
+        let mut written: Vec<u8> = Vec::new();
+        write_map(&mut written, &map).unwrap();
+
+        let mut count = 0;
+        visit_map(&written, |tile| {
+            let expected = map.get_tile(tile.position).unwrap();
+            assert_eq!(tile.house_id, expected.house_id);
+            assert_eq!(tile.flags, expected.flags);
+            assert_eq!(tile.items, &expected.items[..]);
+            count += 1;
+        }).unwrap();
+        assert_eq!(count, map.tiles.len());
+    }
+
+    #[test]
+    fn parse_item_attributes() {
+        let data: &[u8] = &[
+            0x05, 0xE9, 0x03, // unique id 1001
+            0x06, 0x02, 0x00, b'h', b'i', // text "hi"
+            0x08, 0x71, 0x7E, 0xF1, 0x7D, 0x07, // teleport destination 32369, 32241, 7
+            0x01, // unknown
+        ];
+        let mut reader = MemReader::new(data);
+        let mut attributes = Vec::new();
+        for _ in 0..3 {
+            let attribute = reader.get::<u8>().unwrap();
+            attributes.push(ItemAttribute::parse(attribute, &mut reader).unwrap());
+        }
+        assert_eq!(attributes, [
+            ItemAttribute::UniqueId(1001),
+            ItemAttribute::Text("hi".to_string()),
+            ItemAttribute::TeleportDestination(Position::new(32369, 32241, 7)),
+        ]);
+
+        let attribute = reader.get::<u8>().unwrap();
+        match ItemAttribute::parse(attribute, &mut reader) {
+            Err(Error::UnknownAttribute { attribute: 0x01, offset: 14 }) => {},
+            x => panic!("expected unknown attribute, got {:?}", x)
+        }
+    }
+
+    #[test]
+    fn latin1_text() {
+        let data: &[u8] = &[0x02, 0x00, b'f', 0xE9]; // "fé"
+        let text = ItemAttribute::parse(ItemAttributeType::Text as u8, &mut MemReader::new(data)).unwrap();
+        assert_eq!(text, ItemAttribute::Text("f\u{E9}".to_string()));
+
+        let mut written: Vec<u8> = Vec::new();
+        text.write(&mut written).unwrap();
+        assert_eq!(written[1..], data[..]);
+
+        assert!(ItemAttribute::Text("\u{20AC}".to_string()).write(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        match parse_map(&[1, 2, 3, 4]) {
+            Err(Error::BadMagic { magic: 0x0403_0201, offset: 0 }) => {},
+            x => panic!("expected bad magic, got {:?}", x.err())
+        }
+
+        let mut map = test_map();
+        map.version = 4;
+        let mut written: Vec<u8> = Vec::new();
+        write_map(&mut written, &map).unwrap();
+        match parse_map(&written) {
+            Err(Error::UnsupportedVersion { version: 4, offset: 6 }) => {},
+            x => panic!("expected unsupported version, got {:?}", x.err())
+        }
+
+        let mut written: Vec<u8> = Vec::new();
+        write_map(&mut written, &test_map()).unwrap();
+        let size = written.len() - 1;
+        match parse_map(&written[..size]) {
+            Err(Error::Truncated { offset }) => assert_eq!(offset, size),
+            x => panic!("expected truncated data, got {:?}", x.err())
+        }
+
+        let mut written: Vec<u8> = vec![0, 0, 0, 0];
+        write_node_start(&mut written, NodeType::MapHeader as u8, &[2, 0, 0, 0, 0, 1, 0, 1, 3, 0, 0, 0, 57, 0, 0, 0]).unwrap();
+        write_node_end(&mut written).unwrap();
+        match parse_map(&written) {
+            Err(Error::MissingNode { node_type: 0x02, offset: 4 }) => {},
+            x => panic!("expected a missing map data node, got {:?}", x.err())
+        }
+
+        // a tile right below the map data
+        written.pop();
+        write_node_start(&mut written, NodeType::MapData as u8, &[]).unwrap();
+        write_node_start(&mut written, NodeType::Tile as u8, &[1, 2]).unwrap();
+        for _ in 0..3 {
+            write_node_end(&mut written).unwrap();
+        }
+        match parse_map(&written) {
+            Err(Error::UnexpectedNode { node_type: 0x05, offset: 24 }) => {},
+            x => panic!("expected an unexpected tile node, got {:?}", x.err())
+        }
+        match visit_map(&written, |_| {}) {
+            Err(Error::UnexpectedNode { node_type: 0x05, offset: 24 }) => {},
+            x => panic!("expected an unexpected tile node, got {:?}", x.err())
+        }
+
+        // a tile past the east border of the map
+        let mut written: Vec<u8> = vec![0, 0, 0, 0];
+        write_node_start(&mut written, NodeType::MapHeader as u8, &[2, 0, 0, 0, 0, 1, 0, 1, 3, 0, 0, 0, 57, 0, 0, 0]).unwrap();
+        write_node_start(&mut written, NodeType::MapData as u8, &[]).unwrap();
+        write_node_start(&mut written, NodeType::TileArea as u8, &[0xFF, 0xFF, 0, 0, 7]).unwrap();
+        write_node_start(&mut written, NodeType::Tile as u8, &[1, 0]).unwrap();
+        for _ in 0..4 {
+            write_node_end(&mut written).unwrap();
+        }
+        match parse_map(&written) {
+            Err(Error::PositionOverflow { offset: 33 }) => {},
+            x => panic!("expected a position overflow, got {:?}", x.err())
+        }
+        match visit_map(&written, |_| {}) {
+            Err(Error::PositionOverflow { offset: 33 }) => {},
+            x => panic!("expected a position overflow, got {:?}", x.err())
         }
     }
 }
@@ -0,0 +1,272 @@
+// https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/client/minimap.cpp
+// the minimap is stored in blocks of 64x64 tiles, each compressed with zlib
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use image::{ImageBuffer};
+
+use dat::{DatAttributesHeader, Things};
+use draw::*;
+use error::Error;
+use mem_read::*;
+use mem_write::*;
+use minimap::{minimap_color, tile_color};
+use otb::OtbItems;
+use otbm::{Map, Position};
+use rect::Rect;
+use render::stacked_things;
+
+const SIGNATURE: u32 = 0x4D4D_544F; // OTMM
+const VERSION: u16 = 1;
+const DESCRIPTION: &str = "OTMM 1.0";
+
+pub const BLOCK_SIZE: u16 = 64;
+const BLOCK_BYTES: usize = BLOCK_SIZE as usize * BLOCK_SIZE as usize * 3;
+
+pub const FLAG_WAS_SEEN: u8 = 1;
+pub const FLAG_NOT_PATHABLE: u8 = 2;
+pub const FLAG_NOT_WALKABLE: u8 = 4;
+
+/// Color of tiles that have never been seen or have no minimap color.
+pub const NO_COLOR: u8 = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MinimapTile {
+    pub flags: u8,
+    pub color: u8,
+    pub speed: u8,
+}
+
+impl Default for MinimapTile {
+    fn default() -> MinimapTile {
+        MinimapTile { flags: 0, color: NO_COLOR, speed: 10 }
+    }
+}
+
+#[derive(Default)]
+pub struct Otmm {
+    /// Blocks by the position of their top left tile.
+    blocks: HashMap<Position, Vec<MinimapTile>>,
+}
+
+impl Otmm {
+    pub fn new() -> Otmm {
+        Otmm { blocks: HashMap::new() }
+    }
+
+    /// Builds a fully explored minimap: colors are the topmost minimap colors,
+    /// speeds come from the ground and flags from the dat of all items.
+    pub fn from_map(map: &Map, dat: &Things, otb: &OtbItems) -> Otmm {
+        let mut otmm = Otmm::new();
+        for (&position, tile) in &map.tiles {
+            let things = stacked_things(tile, dat, otb);
+            let mut minimap_tile = MinimapTile {
+                flags: FLAG_WAS_SEEN,
+                color: tile_color(tile, dat, otb).unwrap_or(NO_COLOR),
+                speed: 10,
+            };
+
+            for (_, thing) in things {
+                if let Some(speed) = thing.ground_speed() {
+                    minimap_tile.speed = std::cmp::min((speed as u32).div_ceil(10), 255) as u8;
+                }
+                if thing.has_attribute(DatAttributesHeader::NotWalkable) {
+                    minimap_tile.flags |= FLAG_NOT_WALKABLE;
+                }
+                if thing.has_attribute(DatAttributesHeader::NotPathable) {
+                    minimap_tile.flags |= FLAG_NOT_PATHABLE;
+                }
+            }
+
+            otmm.set_tile(position, minimap_tile);
+        }
+        otmm
+    }
+
+    pub fn get_tile(&self, position: Position) -> Option<&MinimapTile> {
+        let (block, index) = Otmm::block_index(position);
+        self.blocks.get(&block).map(|x| &x[index])
+    }
+
+    pub fn set_tile(&mut self, position: Position, tile: MinimapTile) {
+        let (block, index) = Otmm::block_index(position);
+        self.blocks.entry(block).or_insert_with(|| vec![MinimapTile::default(); BLOCK_SIZE as usize * BLOCK_SIZE as usize])[index] = tile;
+    }
+
+    fn block_index(position: Position) -> (Position, usize) {
+        let block = Position::new(position.x - position.x % BLOCK_SIZE, position.y - position.y % BLOCK_SIZE, position.z);
+        let index = (position.y % BLOCK_SIZE) as usize * BLOCK_SIZE as usize + (position.x % BLOCK_SIZE) as usize;
+        (block, index)
+    }
+
+    /// Renders floor `z` with one pixel per tile, `rect` is in tile coordinates.
+    /// Tiles without a color stay transparent.
+    pub fn render(&self, z: u8, rect: Rect) -> Image {
+        let mut image: Image = ImageBuffer::new(rect.width as u32, rect.height as u32);
+        for (block, tiles) in self.blocks.iter().filter(|(x, _)| x.z == z) {
+            for (index, tile) in tiles.iter().enumerate().filter(|(_, x)| x.color != NO_COLOR) {
+                let x = block.x as i32 + (index % BLOCK_SIZE as usize) as i32 - rect.x;
+                let y = block.y as i32 + (index / BLOCK_SIZE as usize) as i32 - rect.y;
+                if x >= 0 && y >= 0 && x < rect.width && y < rect.height {
+                    image.put_pixel(x as u32, y as u32, minimap_color(tile.color));
+                }
+            }
+        }
+        image
+    }
+}
+
+pub fn parse(filename: String) -> Result<Otmm, Error> {
+    let mut file = File::open(filename)?;
+    let mut data: Vec<u8> = Vec::new();
+    file.read_to_end(&mut data)?;
+    parse_otmm(&data)
+}
+
+pub fn parse_otmm(data: &[u8]) -> Result<Otmm, Error> {
+    let data = &mut MemReader::new(data);
+    let signature = data.get::<u32>()?;
+    if signature != SIGNATURE {
+        return Err(Error::BadMagic { magic: signature, offset: 0 });
+    }
+
+    let start = data.get::<u16>()?;
+    let version = data.get::<u16>()?;
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion { version: version as u32, offset: data.position() - 2 });
+    }
+    data.get::<u32>()?; // flags
+    data.get_str()?; // description
+    data.skip((start as usize).saturating_sub(data.position()))?;
+
+    let mut otmm = Otmm::new();
+    loop {
+        let position = Position::new(data.get()?, data.get()?, data.get()?);
+        // the end is marked with an invalid position
+        if position.z > 15 {
+            break;
+        }
+
+        let size = data.get::<u16>()?;
+        let offset = data.position();
+        let mut compressed = vec![0u8; size as usize];
+        data.read_bytes(&mut compressed)?;
+
+        let mut bytes = Vec::with_capacity(BLOCK_BYTES);
+        // one byte more than a block is enough to tell that it is too large
+        ZlibDecoder::new(&compressed[..]).take(BLOCK_BYTES as u64 + 1).read_to_end(&mut bytes)?;
+        if bytes.len() != BLOCK_BYTES {
+            return Err(Error::Truncated { offset: offset + size as usize });
+        }
+
+        let tiles = bytes.chunks(3).map(|x| MinimapTile { flags: x[0], color: x[1], speed: x[2] }).collect();
+        otmm.blocks.insert(Otmm::block_index(position).0, tiles);
+    }
+
+    Ok(otmm)
+}
+
+pub fn write(filename: String, otmm: &Otmm) -> Result<(), Error> {
+    let mut data: Vec<u8> = Vec::new();
+    write_otmm(&mut data, otmm)?;
+    File::create(filename)?.write_all(&data)?;
+    Ok(())
+}
+
+pub fn write_otmm<T: Write>(data: &mut T, otmm: &Otmm) -> Result<(), Error> {
+    data.put(SIGNATURE)?;
+    data.put((4 + 2 + 2 + 4 + 2 + DESCRIPTION.len()) as u16)?; // start of the blocks
+    data.put(VERSION)?;
+    data.put(0u32)?; // flags
+    data.put_str(DESCRIPTION)?;
+
+    // blocks are written in a fixed order so that writing the same minimap twice gives identical bytes
+    let mut blocks = otmm.blocks.iter().collect::<Vec<_>>();
+    blocks.sort_by_key(|(x, _)| (x.z, x.y, x.x));
+
+    for (position, tiles) in blocks {
+        let mut bytes = Vec::with_capacity(BLOCK_BYTES);
+        for tile in tiles {
+            bytes.extend_from_slice(&[tile.flags, tile.color, tile.speed]);
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(3));
+        encoder.write_all(&bytes)?;
+        let compressed = encoder.finish()?;
+
+        data.put(position.x)?;
+        data.put(position.y)?;
+        data.put(position.z)?;
+        data.put(compressed.len() as u16)?;
+        data.write_all(&compressed)?;
+    }
+
+    data.put(u16::MAX)?;
+    data.put(u16::MAX)?;
+    data.put(u8::MAX)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_parse_round_trip() {
+        let mut otmm = Otmm::new();
+        let grass = MinimapTile { flags: FLAG_WAS_SEEN, color: 24, speed: 15 };
+        let wall = MinimapTile { flags: FLAG_WAS_SEEN | FLAG_NOT_WALKABLE | FLAG_NOT_PATHABLE, color: 186, speed: 10 };
+        otmm.set_tile(Position::new(32369, 32241, 7), grass);
+        otmm.set_tile(Position::new(32370, 32241, 7), wall);
+        otmm.set_tile(Position::new(100, 100, 8), grass);
+
+        let mut written: Vec<u8> = Vec::new();
+        write_otmm(&mut written, &otmm).unwrap();
+
+        let otmm = parse_otmm(&written).unwrap();
+        assert_eq!(otmm.blocks.len(), 2);
+        assert_eq!(otmm.get_tile(Position::new(32369, 32241, 7)), Some(&grass));
+        assert_eq!(otmm.get_tile(Position::new(32370, 32241, 7)), Some(&wall));
+        assert_eq!(otmm.get_tile(Position::new(32371, 32241, 7)), Some(&MinimapTile::default()));
+        assert_eq!(otmm.get_tile(Position::new(32369, 32241, 6)), None);
+
+        let image = otmm.render(7, Rect::new(32368, 32240, 4, 4));
+        assert_eq!(image.get_pixel(1, 1).data, [0, 204, 0, 255]);
+        assert_eq!(image.get_pixel(2, 1).data, [255, 51, 0, 255]);
+        assert_eq!(image.get_pixel(3, 1).data, [0, 0, 0, 0]);
+
+        match parse_otmm(&written[..written.len() - 1]) {
+            Err(Error::Truncated { .. }) => {},
+            x => panic!("expected truncated data, got {:?}", x.err())
+        }
+    }
+
+    #[test]
+    fn oversized_block() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(3));
+        encoder.write_all(&vec![0u8; BLOCK_BYTES * 4]).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut data: Vec<u8> = Vec::new();
+        data.put(SIGNATURE).unwrap();
+        data.put(0u16).unwrap();
+        data.put(VERSION).unwrap();
+        data.put(0u32).unwrap();
+        data.put_str(DESCRIPTION).unwrap();
+        data.put(0u16).unwrap();
+        data.put(0u16).unwrap();
+        data.put(7u8).unwrap();
+        data.put(compressed.len() as u16).unwrap();
+        data.write_all(&compressed).unwrap();
+
+        match parse_otmm(&data) {
+            Err(Error::Truncated { .. }) => {},
+            x => panic!("expected an oversized block, got {:?}", x.err())
+        }
+    }
+}
@@ -0,0 +1,218 @@
+// https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/client/creature.cpp#L167
+
+use image::{ImageBuffer, Rgba};
+
+use dat::*;
+use draw::*;
+use point::Point;
+use spr::SpriteData;
+
+const HSI_SI_VALUES: u8 = 7;
+const HSI_H_STEPS: u8 = 19;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum Direction {
+    North = 0,
+    East = 1,
+    South = 2,
+    West = 3,
+}
+
+// https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/client/outfit.cpp#L34
+/// Converts one of the 133 outfit colors to rgb, unknown colors are treated as 0.
+pub fn outfit_color(color: u8) -> Rgba<u8> {
+    let color = if color >= HSI_H_STEPS * HSI_SI_VALUES { 0 } else { color };
+
+    let (hue, saturation, intensity) = if color % HSI_H_STEPS != 0 {
+        let (saturation, intensity) = match color / HSI_H_STEPS {
+            0 => (0.25, 1.00),
+            1 => (0.25, 0.75),
+            2 => (0.50, 0.75),
+            3 => (0.667, 0.75),
+            4 => (1.00, 1.00),
+            5 => (1.00, 0.75),
+            _ => (1.00, 0.50),
+        };
+        ((color % HSI_H_STEPS) as f32 / 18.0, saturation, intensity)
+    } else {
+        (0.0, 0.0, 1.0 - color as f32 / HSI_H_STEPS as f32 / HSI_SI_VALUES as f32)
+    };
+
+    if intensity == 0.0 {
+        return Rgba([0, 0, 0, 255]);
+    }
+    if saturation == 0.0 {
+        let gray = (intensity * 255.0) as u8;
+        return Rgba([gray, gray, gray, 255]);
+    }
+
+    let (red, green, blue);
+    if hue < 1.0 / 6.0 {
+        red = intensity;
+        blue = intensity * (1.0 - saturation);
+        green = blue + (intensity - blue) * 6.0 * hue;
+    } else if hue < 2.0 / 6.0 {
+        green = intensity;
+        blue = intensity * (1.0 - saturation);
+        red = green - (intensity - blue) * (6.0 * hue - 1.0);
+    } else if hue < 3.0 / 6.0 {
+        green = intensity;
+        red = intensity * (1.0 - saturation);
+        blue = red + (intensity - red) * (6.0 * hue - 2.0);
+    } else if hue < 4.0 / 6.0 {
+        blue = intensity;
+        red = intensity * (1.0 - saturation);
+        green = blue - (intensity - red) * (6.0 * hue - 3.0);
+    } else if hue < 5.0 / 6.0 {
+        blue = intensity;
+        green = intensity * (1.0 - saturation);
+        red = green + (intensity - green) * (6.0 * hue - 4.0);
+    } else {
+        red = intensity;
+        green = intensity * (1.0 - saturation);
+        blue = red - (intensity - green) * (6.0 * hue - 5.0);
+    }
+    Rgba([(red * 255.0) as u8, (green * 255.0) as u8, (blue * 255.0) as u8, 255])
+}
+
+/// Multiplies the pixels of `image` with the outfit color matching the mask
+/// color of the template at the same position.
+fn colorize(image: &mut Image, template: &Image, head: u8, body: u8, legs: u8, feet: u8) {
+    let colors = [
+        ([255, 255, 0], outfit_color(head)),
+        ([255, 0, 0], outfit_color(body)),
+        ([0, 255, 0], outfit_color(legs)),
+        ([0, 0, 255], outfit_color(feet)),
+    ];
+
+    for (pixel, mask) in image.pixels_mut().zip(template.pixels()) {
+        if mask.data[3] == 0 {
+            continue;
+        }
+
+        if let Some((_, color)) = colors.iter().find(|(x, _)| x[..] == mask.data[..3]) {
+            for (channel, &tint) in pixel.data[..3].iter_mut().zip(&color.data[..3]) {
+                *channel = (*channel as u16 * tint as u16 / 255) as u8;
+            }
+        }
+    }
+}
+
+/// Renders a creature standing still, with its colors, addons (bit 1 and 2)
+/// and an optional mount, aligned to the bottom right corner of the image.
+/// Returns None if the dat has no such looktype.
+#[allow(clippy::too_many_arguments)]
+pub fn render_outfit(dat: &Things, spr: &SpriteData, looktype: u16, head: u8, body: u8, legs: u8, feet: u8, addons: u8, direction: Direction, mount: u16) -> Option<Image> {
+    let creatures = dat.get(&ThingCategory::Creature)?;
+    let outfit = creatures.get(&looktype)?;
+    let mount = match mount {
+        0 => None,
+        x => creatures.get(&x),
+    };
+
+    let group = FrameGroupType::Idle;
+    let x = direction as u8;
+    let base = outfit.get_image(spr, group, 0, x, 0, 0, 0)?;
+
+    // the bottom right corners of the mount and the outfit: otclient moves the
+    // outfit by the mount's displacement and back by its own one, and every
+    // thing is drawn moved by its own displacement, so the mount ends up one
+    // mount displacement up and left of the outfit
+    let mount_image = mount.and_then(|mount| mount.get_image(spr, group, 0, x, 0, 0, 0));
+    let shift = mount.map(|x| x.displacement()).unwrap_or(Point::new(0, 0));
+    let (mount_corner, outfit_corner) = (Point::new(-shift.x, -shift.y), Point::new(0, 0));
+
+    let (mut left, mut top) = (outfit_corner.x - base.width() as i32, outfit_corner.y - base.height() as i32);
+    let (mut right, mut bottom) = (outfit_corner.x, outfit_corner.y);
+    if let Some(mount_image) = &mount_image {
+        left = std::cmp::min(left, mount_corner.x - mount_image.width() as i32);
+        top = std::cmp::min(top, mount_corner.y - mount_image.height() as i32);
+        right = std::cmp::max(right, mount_corner.x);
+        bottom = std::cmp::max(bottom, mount_corner.y);
+    }
+    let size = std::cmp::max(right - left, bottom - top);
+    let dest = |corner: Point, image: &Image| Point::new(
+        size - (right - corner.x) - image.width() as i32,
+        size - (bottom - corner.y) - image.height() as i32);
+
+    let mut image: Image = ImageBuffer::new(size as u32, size as u32);
+    if let Some(mount_image) = &mount_image {
+        image.blit(dest(mount_corner, mount_image), mount_image);
+    }
+
+    let (_, pattern_height, pattern_depth) = outfit.pattern_size();
+    let z = if mount_image.is_some() { std::cmp::min(1, pattern_depth - 1) as u8 } else { 0 };
+    for y in 0..pattern_height as u8 {
+        if y > 0 && addons & (1 << (y - 1)) == 0 {
+            continue;
+        }
+
+        let mut layer = outfit.get_image(spr, group, 0, x, y, z, 0)?;
+        if outfit.layers(group) > 1 {
+            if let Some(template) = outfit.get_image(spr, group, 1, x, y, z, 0) {
+                colorize(&mut layer, &template, head, body, legs, feet);
+            }
+        }
+
+        image.blit(dest(outfit_corner, &layer), &layer);
+    }
+
+    Some(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use mem_read::MemReader;
+    use version::ClientVersion;
+
+    #[test]
+    fn outfit_palette() {
+        assert_eq!(outfit_color(0).data, [255, 255, 255, 255]);
+        assert_eq!(outfit_color(19).data, [218, 218, 218, 255]);
+        assert_eq!(outfit_color(1).data, [255, 212, 191, 255]);
+        assert_eq!(outfit_color(132).data, [127, 0, 0, 255]);
+        assert_eq!(outfit_color(133), outfit_color(0));
+    }
+
+    #[test]
+    fn colorize_template() {
+        let mut image: Image = ImageBuffer::from_pixel(2, 1, Rgba([200, 100, 50, 255]));
+        let mut template: Image = ImageBuffer::new(2, 1);
+        template.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+
+        colorize(&mut image, &template, 0, 132, 0, 0);
+        assert_eq!(image.get_pixel(0, 0).data, [99, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).data, [200, 100, 50, 255]);
+    }
+
+    #[test]
+    fn mounted_outfit() {
+        let data: &[u8] = &[
+            99, 0, 2, 0, 0, 0, 0, 0, // item, creature, effect and missile counts
+            24, 8, 0, 8, 0, 255, 1, 1, 1, 1, 1, 1, 1, 1, 0, // creature 1: displacement 8, sprite 1
+            24, 16, 0, 16, 0, 255, 1, 1, 1, 1, 1, 1, 1, 2, 0, // creature 2: displacement 16, sprite 2
+        ];
+        let dat = parse_items(&mut MemReader::new(data), DatOptions::from(ClientVersion(860))).unwrap();
+
+        let mut sprites = HashMap::new();
+        sprites.insert(1, ImageBuffer::from_pixel(32, 32, Rgba([255, 0, 0, 255])));
+        sprites.insert(2, ImageBuffer::from_pixel(32, 32, Rgba([0, 0, 255, 255])));
+        let spr = SpriteData { signature: 0, sprites };
+
+        // the outfit is the mount displacement down and right of the mount,
+        // its own displacement does not matter
+        let image = render_outfit(&dat, &spr, 1, 0, 0, 0, 0, 0, Direction::South, 2).unwrap();
+        assert_eq!(image.dimensions(), (48, 48));
+        assert_eq!(image.get_pixel(0, 0).data, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(20, 20).data, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(47, 47).data, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(40, 4).data, [0, 0, 0, 0]);
+        assert_eq!(image.get_pixel(4, 40).data, [0, 0, 0, 0]);
+
+        let image = render_outfit(&dat, &spr, 1, 0, 0, 0, 0, 0, Direction::South, 0).unwrap();
+        assert_eq!(image.dimensions(), (32, 32));
+    }
+}
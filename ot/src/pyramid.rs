@@ -0,0 +1,152 @@
+// https://wiki.openstreetmap.org/wiki/Slippy_map_tilenames
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::{ImageBuffer, Rgba};
+use rayon::prelude::*;
+
+use dat::Things;
+use draw::*;
+use error::Error;
+use otb::OtbItems;
+use otbm::Map;
+use point::Point;
+use rect::Rect;
+use render::render_floor;
+use spr::SpriteData;
+
+/// Width and height of the exported png tiles.
+pub const TILE_SIZE: u32 = 256;
+/// Map tiles covered by one png tile at the highest zoom level, where every map tile is 32x32 pixels.
+const MAP_TILES: i32 = TILE_SIZE as i32 / 32;
+
+/// The zoom level at which the map is drawn in its original size, the single
+/// png tile of zoom level 0 covers the whole map.
+pub fn max_zoom(map: &Map) -> u32 {
+    let size = map.tiles.keys().map(|x| std::cmp::max(x.x, x.y) as i32 + 1).max().unwrap_or(1);
+    let count = (size + MAP_TILES - 1) / MAP_TILES;
+
+    let mut zoom = 0;
+    while (1 << zoom) < count {
+        zoom += 1;
+    }
+    zoom
+}
+
+/// Exports every floor that has tiles on it, see `export_floor`.
+pub fn export(map: &Map, dat: &Things, spr: &SpriteData, otb: &OtbItems, levels: u32, directory: &Path) -> Result<(), Error> {
+    let mut floors = map.tiles.keys().map(|x| x.z).collect::<Vec<_>>();
+    floors.sort();
+    floors.dedup();
+
+    for z in floors {
+        export_floor(map, dat, spr, otb, z, levels, directory)?;
+    }
+    Ok(())
+}
+
+/// Exports floor `z` as `directory/z/zoom/x/y.png`. The highest zoom level is
+/// rendered, each of the `levels - 1` levels below it is built by downscaling
+/// the one above. Tiles without anything on them are not written.
+pub fn export_floor(map: &Map, dat: &Things, spr: &SpriteData, otb: &OtbItems, z: u8, levels: u32, directory: &Path) -> Result<(), Error> {
+    let max_zoom = max_zoom(map);
+    let min_zoom = max_zoom.saturating_sub(levels.saturating_sub(1));
+    let directory = directory.join(z.to_string());
+
+    // things grow up and to the left, so a png tile can show map tiles of the ones right and below of it
+    let mut tiles = HashSet::new();
+    for position in map.tiles.keys().filter(|x| x.z == z) {
+        let x = position.x as u32 / MAP_TILES as u32;
+        let y = position.y as u32 / MAP_TILES as u32;
+        tiles.insert((x, y));
+        tiles.insert((x.saturating_sub(1), y));
+        tiles.insert((x, y.saturating_sub(1)));
+        tiles.insert((x.saturating_sub(1), y.saturating_sub(1)));
+    }
+
+    let mut written = tiles
+        .into_par_iter()
+        .map(|(x, y)| {
+            let rect = Rect::new(x as i32 * MAP_TILES, y as i32 * MAP_TILES, MAP_TILES, MAP_TILES);
+            save_tile(&directory, max_zoom, x, y, &render_floor(map, dat, spr, otb, z, rect))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    for zoom in (min_zoom..max_zoom).rev() {
+        let children = written.into_iter().flatten().collect::<HashSet<_>>();
+        let parents = children.iter().map(|&(x, y)| (x / 2, y / 2)).collect::<HashSet<_>>();
+
+        written = parents
+            .into_par_iter()
+            .map(|(x, y)| {
+                let mut image: Image = ImageBuffer::new(2 * TILE_SIZE, 2 * TILE_SIZE);
+                for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    if children.contains(&(2 * x + dx, 2 * y + dy)) {
+                        let child = image::open(tile_path(&directory, zoom + 1, 2 * x + dx, 2 * y + dy))?.to_rgba();
+                        image.blit(Point::new((dx * TILE_SIZE) as i32, (dy * TILE_SIZE) as i32), &child);
+                    }
+                }
+                save_tile(&directory, zoom, x, y, &downscale(&image))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+    }
+
+    Ok(())
+}
+
+fn tile_path(directory: &Path, zoom: u32, x: u32, y: u32) -> PathBuf {
+    directory.join(zoom.to_string()).join(x.to_string()).join(format!("{}.png", y))
+}
+
+/// Writes the tile unless it is fully transparent, returns its coordinates if it was written.
+fn save_tile(directory: &Path, zoom: u32, x: u32, y: u32, image: &Image) -> Result<Option<(u32, u32)>, Error> {
+    if image.pixels().all(|pixel| pixel.data[3] == 0) {
+        return Ok(None);
+    }
+
+    let path = tile_path(directory, zoom, x, y);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    image.save(path)?;
+    Ok(Some((x, y)))
+}
+
+/// Halves the size of the image, every pixel is the average of a 2x2 block
+/// weighted by alpha so transparent pixels do not darken the edges.
+fn downscale(image: &Image) -> Image {
+    ImageBuffer::from_fn(image.width() / 2, image.height() / 2, |x, y| {
+        let mut sum = [0u32; 4];
+        for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let pixel = image.get_pixel(2 * x + dx, 2 * y + dy).data;
+            let alpha = pixel[3] as u32;
+            for (total, &channel) in sum.iter_mut().zip(&pixel[..3]) {
+                *total += channel as u32 * alpha;
+            }
+            sum[3] += alpha;
+        }
+
+        if sum[3] == 0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        Rgba([(sum[0] / sum[3]) as u8, (sum[1] / sum[3]) as u8, (sum[2] / sum[3]) as u8, (sum[3] / 4) as u8])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downscale_ignores_transparent_pixels() {
+        let mut image: Image = ImageBuffer::new(2, 2);
+        image.put_pixel(0, 0, Rgba([200, 100, 0, 255]));
+        image.put_pixel(1, 1, Rgba([100, 50, 0, 255]));
+
+        let small = downscale(&image);
+        assert_eq!(small.dimensions(), (1, 1));
+        assert_eq!(small.get_pixel(0, 0).data, [150, 75, 0, 127]);
+    }
+}
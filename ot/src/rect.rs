@@ -0,0 +1,30 @@
+use std::ops::*;
+
+pub type Rect = TRect<i32>;
+
+#[derive(Copy, Clone, Debug)]
+pub struct TRect<T> {
+    pub x: T,
+    pub y: T,
+    pub width: T,
+    pub height: T,
+}
+
+impl <T: Add<Output = T> + Copy> TRect<T> {
+    pub fn new(x: T, y: T, width: T, height: T) -> TRect<T> {
+        TRect {
+            x,
+            y,
+            width,
+            height
+        }
+    }
+
+    pub fn right(&self) -> T {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> T {
+        self.y + self.height
+    }
+}
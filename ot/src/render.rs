@@ -0,0 +1,208 @@
+// https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/client/tile.cpp#L50
+
+use image::{ImageBuffer};
+
+use dat::*;
+use draw::*;
+use otb::OtbItems;
+use otbm::{ItemAttribute, ItemNode, Map, Position, Tile};
+use point::*;
+use rect::*;
+use spr::*;
+
+const TILE_SIZE: i32 = 32;
+const MAX_ELEVATION: i32 = 24;
+/// Things are at most 2x2 tiles plus their displacement, so tiles this far to
+/// the right or bottom of the rendered area can still reach into it.
+const MAX_OVERDRAW: i32 = 3;
+
+const GROUND_FLOOR: u8 = 7;
+const MAX_FLOOR: u8 = 15;
+/// How many floors below the current one are visible underground.
+const UNDERGROUND_VIEW: u8 = 2;
+/// Brightness kept for every floor that is drawn above another one.
+const FLOOR_DIM: f32 = 0.7;
+
+/// Renders floor `z` of the map, `rect` is in tile coordinates and every tile is
+/// drawn as 32x32 pixels.
+pub fn render_floor(map: &Map, dat: &Things, spr: &SpriteData, otb: &OtbItems, z: u8, rect: Rect) -> Image {
+    let mut image = new_canvas(rect);
+    draw_floor(&mut image, map, dat, spr, otb, z, rect);
+    image
+}
+
+/// Renders floor `z` together with the floors below it that the client shows:
+/// everything down to the ground floor 7 on the surface, and up to two floors
+/// further down when underground. Each floor is shifted one tile up and to the
+/// left of the one below it, lower floors can optionally be dimmed.
+pub fn render_floors(map: &Map, dat: &Things, spr: &SpriteData, otb: &OtbItems, z: u8, rect: Rect, dim: bool) -> Image {
+    let last_floor = last_visible_floor(z);
+    let mut image = new_canvas(rect);
+    for floor in (z..=last_floor).rev() {
+        if dim && floor != last_floor {
+            darken(&mut image, FLOOR_DIM);
+        }
+
+        let offset = floor as i32 - z as i32;
+        draw_floor(&mut image, map, dat, spr, otb, floor, Rect::new(rect.x - offset, rect.y - offset, rect.width, rect.height));
+    }
+    image
+}
+
+fn last_visible_floor(z: u8) -> u8 {
+    if z <= GROUND_FLOOR {
+        GROUND_FLOOR
+    } else {
+        std::cmp::min(z.saturating_add(UNDERGROUND_VIEW), MAX_FLOOR)
+    }
+}
+
+fn new_canvas(rect: Rect) -> Image {
+    ImageBuffer::new((rect.width * TILE_SIZE) as u32, (rect.height * TILE_SIZE) as u32)
+}
+
+fn darken(image: &mut Image, factor: f32) {
+    for pixel in image.pixels_mut() {
+        for channel in &mut pixel.data[..3] {
+            *channel = (*channel as f32 * factor) as u8;
+        }
+    }
+}
+
+fn draw_floor(image: &mut Image, map: &Map, dat: &Things, spr: &SpriteData, otb: &OtbItems, z: u8, rect: Rect) {
+    for y in rect.y..rect.bottom() + MAX_OVERDRAW {
+        for x in rect.x..rect.right() + MAX_OVERDRAW {
+            if x < 0 || y < 0 || x > u16::MAX as i32 || y > u16::MAX as i32 {
+                continue;
+            }
+
+            if let Some(tile) = map.get_tile(Position::new(x as u16, y as u16, z)) {
+                let dest = Point::new((x - rect.x) * TILE_SIZE, (y - rect.y) * TILE_SIZE);
+                draw_tile(image, tile, dat, spr, otb, dest);
+            }
+        }
+    }
+}
+
+/// Draws a tile the way the client stacks it: ground, borders and bottom items
+/// first, then common items, then top items. Everything but the top items is
+/// lifted by the elevation of the things below it.
+fn draw_tile(image: &mut Image, tile: &Tile, dat: &Things, spr: &SpriteData, otb: &OtbItems, dest: Point) {
+    let mut elevation = 0;
+    for (item, thing) in stacked_things(tile, dat, otb) {
+        let (x, y, z) = get_pattern(tile, item, thing, dat, otb);
+        if thing.stack_priority() == 3 {
+            thing.draw(spr, image, dest, x, y, z);
+        } else {
+            thing.draw(spr, image, Point::new(dest.x - elevation, dest.y - elevation), x, y, z);
+            elevation = std::cmp::min(elevation + thing.elevation(), MAX_ELEVATION);
+        }
+    }
+}
+
+/// The items of a tile with their dat things, in the order they are drawn.
+/// Items without a client id or dat entry are left out.
+pub(crate) fn stacked_things<'a>(tile: &'a Tile, dat: &'a Things, otb: &OtbItems) -> Vec<(&'a ItemNode, &'a Thing)> {
+    let items = match dat.get(&ThingCategory::Item) {
+        Some(items) => items,
+        None => return Vec::new()
+    };
+
+    let mut things = tile.items.iter()
+        .filter_map(|item| otb.get_client_id(item.id())
+            .and_then(|client_id| items.get(&client_id))
+            .map(|thing| (item, thing)))
+        .collect::<Vec<_>>();
+    things.sort_by_key(|(_, thing)| match thing.stack_priority() {
+        3 => 6, // top items go above everything else
+        x => x,
+    });
+    things
+}
+
+// https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/client/item.cpp#L384
+fn get_pattern(tile: &Tile, item: &ItemNode, thing: &Thing, dat: &Things, otb: &OtbItems) -> (i32, i32, i32) {
+    let (width, height, _) = thing.pattern_size();
+    let count = item.attributes().iter().filter_map(|x| match x {
+        ItemAttribute::Count(count) => Some(*count as i32),
+        _ => None
+    }).next().unwrap_or(0);
+
+    if thing.has_attribute(DatAttributesHeader::Stackable) && width == 4 && height == 2 {
+        match count {
+            x if x <= 0 => (0, 0, 0),
+            x if x < 5 => (x - 1, 0, 0),
+            x if x < 10 => (0, 1, 0),
+            x if x < 25 => (1, 1, 0),
+            x if x < 50 => (2, 1, 0),
+            _ => (3, 1, 0),
+        }
+    } else if thing.has_attribute(DatAttributesHeader::Hangable) {
+        // hangables are drawn depending on the wall they are attached to
+        let has_hook = |hook| tile.items.iter()
+            .filter_map(|x| otb.get_client_id(x.id()))
+            .filter_map(|x| dat.get(&ThingCategory::Item).and_then(|items| items.get(&x)))
+            .any(|x| x.has_attribute(hook));
+        if has_hook(DatAttributesHeader::HookSouth) {
+            (1, 0, 0)
+        } else if has_hook(DatAttributesHeader::HookEast) {
+            (2, 0, 0)
+        } else {
+            (0, 0, 0)
+        }
+    } else if thing.has_attribute(DatAttributesHeader::Splash) || thing.has_attribute(DatAttributesHeader::FluidContainer) {
+        // server fluid types are client fluid colors, repeated every 8 values
+        let color = count % 8;
+        (color % 4, color / 4, 0)
+    } else {
+        let position = tile.position;
+        (position.x as i32, position.y as i32, position.z as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use image::Rgba;
+    use mem_read::MemReader;
+    use otb::{ItemCategory, ItemType};
+
+    #[test]
+    fn visible_floors() {
+        assert_eq!(last_visible_floor(0), 7);
+        assert_eq!(last_visible_floor(7), 7);
+        assert_eq!(last_visible_floor(8), 10);
+        assert_eq!(last_visible_floor(14), 15);
+    }
+
+    #[test]
+    fn translucent_things() {
+        let data: &[u8] = &[
+            101, 0, 0, 0, 0, 0, 0, 0, // item, creature, effect and missile counts
+            0, 150, 0, 255, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, // item 100: ground, sprite 1
+            24, 255, 1, 1, 1, 1, 1, 1, 1, 2, 0, 0, 0, // item 101: translucent, sprite 2
+        ];
+        let dat = parse_items(&mut MemReader::new(data), DatOptions::default()).unwrap();
+
+        let mut sprites = HashMap::new();
+        sprites.insert(1, ImageBuffer::from_pixel(32, 32, Rgba([0, 0, 255, 255])));
+        sprites.insert(2, ImageBuffer::from_pixel(32, 32, Rgba([255, 0, 0, 255])));
+        let spr = SpriteData { signature: 0, sprites };
+
+        let mut otb = OtbItems::default();
+        otb.insert(ItemCategory::Ground(ItemType::new(100, 100)));
+        otb.insert(ItemCategory::Invalid(ItemType::new(101, 101)));
+
+        let tile = Tile {
+            position: Position::new(100, 100, 7),
+            house_id: None,
+            flags: 0,
+            items: vec![ItemNode::new(100), ItemNode::new(101)],
+        };
+        let mut image = new_canvas(Rect::new(0, 0, 1, 1));
+        draw_tile(&mut image, &tile, &dat, &spr, &otb, Point::new(0, 0));
+        assert_eq!(image.get_pixel(16, 16).data, [128, 0, 128, 255]);
+    }
+}
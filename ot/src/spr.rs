@@ -2,82 +2,222 @@
 
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Error, Read};
+use std::io::Read;
 
+use error::Error;
 use mem_read::*;
 
 use image::{ImageBuffer};
 use rayon::prelude::*;
 
 use draw::*;
+use version::ClientVersion;
 
 const SPRITE_DATA_SIZE: u32 = 32 * 32 * 4;
 
+#[derive(Clone, Copy, Debug)]
+pub struct SprOptions {
+    /// The sprite count is stored as u32 instead of u16.
+    pub extended: bool,
+    /// Colored pixels are stored with an alpha channel, as written by clients with transparency enabled.
+    pub transparency: bool,
+}
+
+impl From<ClientVersion> for SprOptions {
+    /// Transparency is a client setting that can not be told from the version, it is off.
+    fn from(version: ClientVersion) -> SprOptions {
+        SprOptions { extended: version.has_extended_sprites(), transparency: false }
+    }
+}
+
+impl Default for SprOptions {
+    fn default() -> SprOptions {
+        SprOptions::from(ClientVersion::LATEST)
+    }
+}
+
 pub struct SpriteData {
-    pub version: u32,
+    pub signature: u32,
     pub sprites: HashMap<u32, Image>,
 }
 
 impl SpriteData {
+    /// The client the file belongs to, None if the signature is unknown.
+    pub fn version(&self) -> Option<ClientVersion> {
+        ClientVersion::from_spr_signature(self.signature)
+    }
+
     pub fn get_image(&self, id: u32) -> Option<&Image> {
         self.sprites.get(&id)
     }
 }
 
+fn parse_sprite(data: &mut MemReader, options: SprOptions) -> Result<Image, Error> {
+    let mut img: Image = ImageBuffer::new(32, 32);
+
+    data.get::<[u8; 3]>()?; // color key
+    let size = data.get::<u16>()? as usize;
+    if size > data.remaining().len() {
+        return Err(Error::Truncated { offset: data.position() + data.remaining().len() });
+    }
+
+    let channels = if options.transparency { 4 } else { 3 };
+    let mut write = 0;
+    let mut read = 0;
+    let mut i = 0;
+    while read < size && write < SPRITE_DATA_SIZE {
+        let offset = data.position();
+        let transparent_count = data.get::<u16>()?;
+        let colored_count = data.get::<u16>()?;
+        read += 4 + channels * colored_count as usize;
+        if read > size || i + transparent_count as u32 + colored_count as u32 > 32 * 32 {
+            return Err(Error::SpriteOverflow { offset });
+        }
+
+        for _ in 0..transparent_count {
+            img.get_pixel_mut(i % 32, i / 32).data = [0, 0, 0, 0];
+            i += 1;
+        }
+
+        for _ in 0..colored_count {
+            let (r, g, b) = (data.get()?, data.get()?, data.get()?);
+            let a = if options.transparency { data.get()? } else { 255 };
+            img.get_pixel_mut(i % 32, i / 32).data = [r, g, b, a];
+            i += 1;
+        }
+
+        write += 4 * transparent_count as u32 + 4 * colored_count as u32;
+    }
+
+    Ok(img)
+}
+
 pub fn parse(filename: String) -> Result<SpriteData, Error> {
+    parse_with_options(filename, SprOptions::default())
+}
+
+pub fn parse_with_options(filename: String, options: SprOptions) -> Result<SpriteData, Error> {
     let mut file = File::open(filename)?;
     let mut data: Vec<u8> = Vec::new();
     file.read_to_end(&mut data)?;
-    let data: &mut &[u8] = &mut data.as_ref();
-    let begin = data.clone();
+    parse_sprites(&data, options)
+}
+
+pub fn parse_sprites(begin: &[u8], options: SprOptions) -> Result<SpriteData, Error> {
+    let data = &mut MemReader::new(begin);
 
-    let version = data.get()?;
+    let signature = data.get()?;
 
-    let count = data.get::<u32>()?;
+    let count = if options.extended { data.get::<u32>()? } else { data.get::<u16>()? as u32 };
     let mut vec = Vec::with_capacity(count as _);
-    vec.extend((0..count).flat_map(|id| {
-        data.get::<u32>()
-            .ok()
-            .and_then(|x| if x == 0 { None } else { Some((id + 1, x)) })
-    }));
+    for id in 0..count {
+        let address = data.get::<u32>()?;
+        if address != 0 {
+            vec.push((id + 1, address as usize));
+        }
+    }
 
     Ok(SpriteData {
-        version,
+        signature,
         sprites: vec
             .into_par_iter()
-            .map(|n| {
-                let data: &mut &[u8] = &mut &begin[n.1 as _..];
-                let mut img: Image = ImageBuffer::new(32, 32);
-
-                let color_key = data.get::<[u8; 3]>()?;
-                let size = data.get()?;
-
-                let mut write = 0;
-                let mut read = 0;
-                let mut i = 0;
-                while read < size && write < SPRITE_DATA_SIZE {
-                    let transparent_count = data.get::<u16>()?;
-                    let colored_count = data.get::<u16>()?;
-
-                    for _ in 0..transparent_count {
-                        img.get_pixel_mut(i / 32, i % 32).data = [0, 0, 0, 0];
-                        i += 1;
-                    }
-
-                    for _ in 0..colored_count {
-                        let (r, g, b, a) = (data.get()?, data.get()?, data.get()?, 0); // TODO: , 0)?
-                        img.get_pixel_mut(i / 32, i % 32).data = [r, g, b, a];
-                        i += 1;
-                    }
-
-                    write += 4 * transparent_count as u32 + 4 * colored_count as u32;
-                    read += 4 + (3 /* channels */ * colored_count);
-                }
-
-                //img.save(format!("sprites/{}.png", n.0))?;
-
-                Ok((n.0, img))
+            .map(|(id, address)| {
+                let data = begin.get(address..).ok_or(Error::Truncated { offset: address })?;
+                let img = parse_sprite(&mut MemReader::with_offset(data, address), options)?;
+
+                //img.save(format!("sprites/{}.png", id))?;
+
+                Ok((id, img))
             })
             .collect::<Result<_, Error>>()?,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sprite_pixels() {
+        let data: &[u8] = &[
+            0xFF, 0x00, 0xFF, // color key
+            0x07, 0x00, // size
+            0x21, 0x00, 0x01, 0x00, 0x10, 0x20, 0x30, // 33 transparent, 1 colored
+        ];
+        let img = parse_sprite(&mut MemReader::new(data), SprOptions::default()).unwrap();
+        assert_eq!(img.get_pixel(0, 1).data, [0, 0, 0, 0]);
+        assert_eq!(img.get_pixel(1, 1).data, [0x10, 0x20, 0x30, 255]);
+        assert_eq!(img.get_pixel(1, 0).data, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn parse_sprite_overflow() {
+        let data: &[u8] = &[
+            0xFF, 0x00, 0xFF, // color key
+            0x0B, 0x00, // size
+            0xFF, 0x03, 0x00, 0x00, // 1023 transparent
+            0x00, 0x00, 0x02, 0x00, 0x10, 0x20, 0x30, // 2 colored, only 1 pixel is left
+        ];
+        match parse_sprite(&mut MemReader::new(data), SprOptions::default()) {
+            Err(Error::SpriteOverflow { offset: 9 }) => {},
+            x => panic!("expected a sprite overflow, got {:?}", x.err())
+        }
+    }
+
+    #[test]
+    fn parse_sprite_size() {
+        let data: &[u8] = &[
+            0xFF, 0x00, 0xFF, // color key
+            0xFF, 0xFF, // size, far more than there is
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        match parse_sprite(&mut MemReader::new(data), SprOptions::default()) {
+            Err(Error::Truncated { offset: 9 }) => {},
+            x => panic!("expected truncated data, got {:?}", x.err())
+        }
+
+        let data: &[u8] = &[
+            0xFF, 0x00, 0xFF, // color key
+            0x08, 0x00, // size
+            0x00, 0x00, 0x00, 0x00, // empty run
+            0x00, 0x00, 0x01, 0x00, 0x10, 0x20, 0x30, // 1 colored, past the size
+        ];
+        match parse_sprite(&mut MemReader::new(data), SprOptions::default()) {
+            Err(Error::SpriteOverflow { offset: 9 }) => {},
+            x => panic!("expected a sprite overflow, got {:?}", x.err())
+        }
+    }
+
+    #[test]
+    fn parse_sprite_transparency() {
+        let data: &[u8] = &[
+            0xFF, 0x00, 0xFF, // color key
+            0x0C, 0x00, // size
+            0x01, 0x00, 0x02, 0x00, 0x10, 0x20, 0x30, 0x80, 0x40, 0x50, 0x60, 0xFF, // 1 transparent, 2 colored
+        ];
+        let img = parse_sprite(&mut MemReader::new(data), SprOptions { extended: true, transparency: true }).unwrap();
+        assert_eq!(img.get_pixel(0, 0).data, [0, 0, 0, 0]);
+        assert_eq!(img.get_pixel(1, 0).data, [0x10, 0x20, 0x30, 0x80]);
+        assert_eq!(img.get_pixel(2, 0).data, [0x40, 0x50, 0x60, 0xFF]);
+    }
+
+    #[test]
+    fn parse_sprites_count_size() {
+        let data: &[u8] = &[
+            0x94, 0x05, 0x22, 0x4C, // signature
+            0x02, 0x00, // count
+            0x00, 0x00, 0x00, 0x00, 0x0E, 0x00, 0x00, 0x00, // sprite 1 is empty, sprite 2 at 14
+            0xFF, 0x00, 0xFF, 0x07, 0x00, 0x00, 0x00, 0x01, 0x00, 0x10, 0x20, 0x30,
+        ];
+        let options = SprOptions::from(ClientVersion(860));
+        assert!(!options.extended);
+
+        let spr = parse_sprites(data, options).unwrap();
+        assert_eq!(spr.version(), Some(ClientVersion(860)));
+        assert_eq!(spr.sprites.len(), 1);
+        assert_eq!(spr.get_image(2).unwrap().get_pixel(0, 0).data, [0x10, 0x20, 0x30, 255]);
+
+        // the same file read with a u32 count runs into the sprite data
+        assert!(parse_sprites(data, SprOptions::from(ClientVersion(960))).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+// https://github.com/edubart/otclient/blob/1addf3e1766ca3fe43bdf1114c0655a971123291/src/client/game.cpp#L1513
+
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+
+use dat::DatOptions;
+use error::Error;
+use spr::SprOptions;
+
+/// Signatures the dat and spr files of released clients start with, as listed by ItemEditor.
+const SIGNATURES: &[(ClientVersion, u32, u32)] = &[
+    (ClientVersion(740), 0x41BF_619C, 0x41B9_EA86),
+    (ClientVersion(772), 0x439D_5A33, 0x4398_52BE),
+    (ClientVersion(800), 0x467F_D7E6, 0x467F_9E74),
+    (ClientVersion(810), 0x475D_3747, 0x475D_0B01),
+    (ClientVersion(854), 0x4B28_B89E, 0x4B1E_2CAA),
+    (ClientVersion(860), 0x4C2C_7993, 0x4C22_0594),
+    (ClientVersion(870), 0x4CFE_22C5, 0x4CFD_078A),
+    (ClientVersion(910), 0x4E12_DAFF, 0x4E12_DB27),
+    (ClientVersion(960), 0x4FFA_74CC, 0x4FFA_74F9),
+    (ClientVersion(986), 0x5170_E904, 0x5170_E96F),
+    (ClientVersion(1098), 0x0000_42A3, 0x57BB_D603),
+];
+
+/// A client version the way otclient numbers them, e.g. 860 for 8.60.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ClientVersion(pub u16);
+
+impl ClientVersion {
+    /// The newest file layout, which is what the parsers assume by default.
+    pub const LATEST: ClientVersion = ClientVersion(1098);
+
+    /// Things have a z pattern and a displacement with a value.
+    pub fn has_pattern_z(self) -> bool {
+        self.0 >= 755
+    }
+
+    /// Sprite ids and counts are stored as u32 instead of u16.
+    pub fn has_extended_sprites(self) -> bool {
+        self.0 >= 960
+    }
+
+    pub fn from_dat_signature(signature: u32) -> Option<ClientVersion> {
+        SIGNATURES.iter().find(|x| x.1 == signature).map(|x| x.0)
+    }
+
+    pub fn from_spr_signature(signature: u32) -> Option<ClientVersion> {
+        SIGNATURES.iter().find(|x| x.2 == signature).map(|x| x.0)
+    }
+
+    /// Animations have per phase durations.
+    pub fn has_enhanced_animations(self) -> bool {
+        self.0 >= 1050
+    }
+
+    /// Creatures have idle and moving frame groups.
+    pub fn has_frame_groups(self) -> bool {
+        self.0 >= 1057
+    }
+}
+
+impl Default for ClientVersion {
+    fn default() -> ClientVersion {
+        ClientVersion::LATEST
+    }
+}
+
+fn read_signature(filename: String) -> Result<u32, Error> {
+    let mut bytes = [0u8; 4];
+    File::open(filename)?.read_exact(&mut bytes).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => Error::Truncated { offset: 0 },
+        _ => Error::Io(e),
+    })?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Finds the client version from the signatures of a dat and spr file and
+/// returns the options to parse them with. Fails if a signature is unknown or
+/// the files belong to different clients.
+pub fn detect(dat_filename: String, spr_filename: String) -> Result<(DatOptions, SprOptions), Error> {
+    let signature = read_signature(dat_filename)?;
+    let dat = ClientVersion::from_dat_signature(signature).ok_or(Error::UnsupportedVersion { version: signature, offset: 0 })?;
+    let signature = read_signature(spr_filename)?;
+    let spr = ClientVersion::from_spr_signature(signature).ok_or(Error::UnsupportedVersion { version: signature, offset: 0 })?;
+
+    if dat != spr {
+        return Err(Error::VersionMismatch { dat, spr });
+    }
+    Ok((DatOptions::from(dat), SprOptions::from(spr)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures() {
+        assert_eq!(ClientVersion::from_dat_signature(0x4C2C_7993), Some(ClientVersion(860)));
+        assert_eq!(ClientVersion::from_spr_signature(0x4C22_0594), Some(ClientVersion(860)));
+        assert_eq!(ClientVersion::from_spr_signature(0x4C2C_7993), None);
+        assert_eq!(ClientVersion::from_dat_signature(0x42A3), Some(ClientVersion::LATEST));
+    }
+
+    #[test]
+    fn detect_772() {
+        let dir = std::env::temp_dir();
+        let dat = dir.join("ot_detect_772.dat");
+        let spr = dir.join("ot_detect_772.spr");
+        std::fs::write(&dat, 0x439D_5A33u32.to_le_bytes()).unwrap();
+        std::fs::write(&spr, 0x4398_52BEu32.to_le_bytes()).unwrap();
+
+        let (dat_options, spr_options) = detect(dat.to_str().unwrap().to_string(), spr.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(dat_options.version, ClientVersion(772));
+        assert!(!dat_options.extended);
+        assert!(!spr_options.extended);
+
+        std::fs::write(&spr, 0x4C22_0594u32.to_le_bytes()).unwrap();
+        match detect(dat.to_str().unwrap().to_string(), spr.to_str().unwrap().to_string()) {
+            Err(Error::VersionMismatch { dat: ClientVersion(772), spr: ClientVersion(860) }) => {},
+            x => panic!("expected a version mismatch, got {:?}", x.err())
+        }
+    }
+
+    #[test]
+    fn detect_unknown_signature() {
+        let dir = std::env::temp_dir();
+        let dat = dir.join("ot_detect_unknown.dat");
+        let spr = dir.join("ot_detect_unknown.spr");
+        std::fs::write(&dat, 0x1234_5678u32.to_le_bytes()).unwrap();
+        std::fs::write(&spr, 0x4C22_0594u32.to_le_bytes()).unwrap();
+
+        match detect(dat.to_str().unwrap().to_string(), spr.to_str().unwrap().to_string()) {
+            Err(Error::UnsupportedVersion { version: 0x1234_5678, offset: 0 }) => {},
+            x => panic!("expected an unsupported version, got {:?}", x.err())
+        }
+        assert_eq!(ClientVersion::from_spr_signature(0x1234_5678), None);
+    }
+}
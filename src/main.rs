@@ -6,15 +6,25 @@
 #[macro_use]
 extern crate ot;
 
-use ot::*;
+use ot::{dat, otb, otbm, spr, Error};
+use ot::rect::Rect;
+use ot::render::render_floors;
 
-use std::io::{Error, Write};
+use std::io::Write;
 
 fn main() -> Result<(), Error> {
+    // otbmview [x y z width height], by default the area around the first temple is drawn
+    let args = std::env::args().skip(1).filter_map(|x| x.parse::<i32>().ok()).collect::<Vec<_>>();
 
     // -------------
+    // custom clients have unknown signatures, they are read like the latest client
+    let (dat_options, spr_options) = match ot::detect("Tibia.dat".to_string(), "Tibia.spr".to_string()) {
+        Err(Error::UnsupportedVersion { .. }) => Default::default(),
+        x => x?
+    };
+
     write!("Loading otb..");
-    let otb = otb::parse("items.otb".to_string())?;
+    let otb = otb::parse_with_version("items.otb".to_string(), dat_options.version)?;
     println!("done");
 
     write!("Loading otbm.. ");
@@ -22,27 +32,24 @@ fn main() -> Result<(), Error> {
     println!("done");
 
     write!("Loading spr.. ");
-    let spr = spr::parse("Tibia.spr".to_string())?;
+    let spr = spr::parse_with_options("Tibia.spr".to_string(), spr_options)?;
     println!("done");
 
     write!("Loading dat.. ");
-    let dat = dat::parse("Tibia.dat".to_string())?;
+    let dat = dat::parse_with_options("Tibia.dat".to_string(), dat_options)?;
     println!("done");
 
-    //let img: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(32000 * 32, 32000 * 32);
-
-    /*for (id, thing) in &dat[&ThingCategory::Item] {
-        thing.get_texture(&spr).save(format!("test/{}.png", id))?;
-    }*/
-
-    //dat[&ThingCategory::Item][&486].get_texture(&spr).save(format!("test/{}.png", 486))?;
+    let (x, y, z, width, height) = match args[..] {
+        [x, y, z, width, height] => (x, y, z as u8, width, height),
+        _ => {
+            let center = otbm.towns.first().map(|x| x.temple).unwrap_or(otbm::Position::new(0, 0, 7));
+            (center.x as i32 - 16, center.y as i32 - 16, center.z, 32, 32)
+        }
+    };
 
-    /*dat[&ThingCategory::Item][&103].get_texture(&spr).save(format!("test/{}.png", 103))?;
-    spr.get_image(537).save(format!("test/{}-img.png", 537))?;*/
-
-    println!("done!");
-    let mut s = String::new();
-    std::io::stdin().read_line(&mut s)?;
+    write!("Rendering floors.. ");
+    render_floors(&otbm, &dat, &spr, &otb, z, Rect::new(x, y, width, height), false).save("floor.png")?;
+    println!("done");
 
     Ok(())
-}
\ No newline at end of file
+}